@@ -0,0 +1,895 @@
+//! The `dice` command line, kept in the library so that a binary with dice
+//! of its own can reuse it.
+//!
+//! ```no_run
+//! extern crate rcmd;
+//!
+//! use rcmd::{cli, CustomDie, Die, Registry};
+//!
+//! fn main() {
+//!     let registry = Registry::new().with(CustomDie::new("Z", Die::Faces(vec![0, 1, 2, 3, 4, 5])));
+//!     std::process::exit(cli::run(std::env::args().skip(1), &registry));
+//! }
+//! ```
+//!
+//! `dice 4d6kh3 1d20+5` rolls each expression, `dice stats 4d6kh3` prints
+//! the chance of every total instead, and `dice compare 2d6 1d12` sets the
+//! distributions side by side. `dice 6x4d6kh3` rolls six times over,
+//! `dice "sword: 1d8+3 # magic"` labels and comments a roll, `--format json`
+//! prints the rolls as JSON, and `dice -` or `dice --file rolls.txt` rolls a
+//! line at a time.
+
+use std::cmp::Reverse;
+use std::env;
+use std::fmt::Write;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::str::FromStr;
+
+use num_rational::BigRational;
+use num_traits::{One, ToPrimitive, Zero};
+use rand::{OsRng, Rng};
+use terminal_size::Width;
+
+use json::Json;
+#[cfg(feature = "repl")]
+use repl;
+use {Distribution, Estimate, Estimator, EvalError, Expr, ExprResult, Ladder, Odds, ParseError, Registry, Repeat,
+     Rollable, SeededRng};
+
+/// The terminal width assumed when it can't be found out.
+const DEFAULT_WIDTH: usize = 80;
+
+/// The percentiles `dice compare` reports.
+const PERCENTILES: [u32; 7] = [5, 10, 25, 50, 75, 90, 95];
+
+/// What to do with the expressions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Command {
+    Roll,
+    /// Print each distribution (`dice stats`)
+    Stats,
+    /// Compare the distributions (`dice compare`)
+    Compare,
+}
+
+/// How rolls are printed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Format {
+    Text,
+    /// One JSON document covering every roll (`--format json`)
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "json" => Ok(Format::Json),
+            _ => Err(format!("Unknown format: {} (expected text or json)", s)),
+        }
+    }
+}
+
+/// Command line options.
+pub(crate) struct Options {
+    command: Command,
+    format: Format,
+    /// Skip arguments that fail to parse or evaluate instead of reporting them
+    lenient: bool,
+    /// Describe each total on the FATE adjective ladder
+    ladder: bool,
+    /// Roll each d100 as a tens die and a units die
+    percentile: bool,
+    /// Roll reproducibly from this seed instead of a random one
+    pub(crate) seed: Option<u64>,
+    /// Print the seed before the rolls
+    verbose: bool,
+    /// How many rolls an estimated distribution is based on
+    trials: u64,
+    /// How many threads to estimate with, if not one per core
+    threads: Option<usize>,
+    /// The most dice a term may roll, if not the registry's
+    max_dice: Option<u32>,
+    /// The most sides a die may have, if not the registry's
+    max_sides: Option<u32>,
+    /// Roll each line of this file, or of stdin for `-`, instead of `exprs`
+    file: Option<String>,
+    /// How many times to roll each expression
+    times: u32,
+    /// Sort each expression's repetitions from highest to lowest
+    sort: bool,
+    /// Follow each expression's repetitions with their sum, lowest and
+    /// highest
+    aggregate: bool,
+    pub(crate) exprs: Vec<String>,
+}
+
+impl Options {
+    pub(crate) fn from_args<I: Iterator<Item = String>>(args: I) -> Result<Options, String> {
+        let mut opts = Options {
+            command: Command::Roll, format: Format::Text, lenient: false, ladder: false, percentile: false, seed: None, verbose: false,
+            trials: Estimator::DEFAULT_TRIALS, threads: None, max_dice: None, max_sides: None, file: None,
+            times: 1, sort: false, aggregate: false, exprs: Vec::new(),
+        };
+        let mut args = args.peekable();
+        match args.peek().map(String::as_str) {
+            Some("stats") => opts.command = Command::Stats,
+            Some("compare") => opts.command = Command::Compare,
+            _ => {}
+        }
+        if opts.command != Command::Roll {
+            args.next();
+        }
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--lenient" => opts.lenient = true,
+                "--ladder"  => opts.ladder = true,
+                "--percentile" => opts.percentile = true,
+                "--verbose" => opts.verbose = true,
+                "--sort" => opts.sort = true,
+                "--aggregate" => opts.aggregate = true,
+                "--times" => opts.times = number(&arg, args.next())?,
+                "--format" => opts.format = args.next().ok_or("--format needs text or json")?.parse()?,
+                "--seed" => opts.seed = Some(number(&arg, args.next())?),
+                "--trials" => opts.trials = number(&arg, args.next())?,
+                "--threads" => opts.threads = Some(number(&arg, args.next())?),
+                "--max-dice" => opts.max_dice = Some(number(&arg, args.next())?),
+                "--max-sides" => opts.max_sides = Some(number(&arg, args.next())?),
+                "--file" => opts.file = Some(args.next().ok_or("--file needs a file name, or - for stdin")?),
+                "-" => opts.file = Some(arg),
+                _ if arg.starts_with("--") => return Err(format!("Unknown option: {}", arg)),
+                // A label written apart from its expression, as in `sword: 1d8+3`
+                _ => match opts.exprs.last_mut() {
+                    Some(last) if last.ends_with(':') => {
+                        last.push(' ');
+                        last.push_str(&arg);
+                    }
+                    _ => opts.exprs.push(arg),
+                },
+            }
+        }
+        if opts.format == Format::Json && opts.command != Command::Roll {
+            return Err("--format json only works when rolling".to_string());
+        }
+        if opts.command != Command::Roll && (opts.times != 1 || opts.sort || opts.aggregate) {
+            return Err("--times, --sort and --aggregate only work when rolling".to_string());
+        }
+        if opts.trials == 0 || opts.trials > Estimator::MAX_TRIALS {
+            return Err(format!("--trials needs a number from 1 to {}", Estimator::MAX_TRIALS));
+        }
+        if opts.times == 0 || opts.times > Registry::DEFAULT_MAX_REPEATS {
+            return Err(format!("--times needs a number from 1 to {}", Registry::DEFAULT_MAX_REPEATS));
+        }
+        if opts.file.is_some() && (opts.command != Command::Roll || !opts.exprs.is_empty()) {
+            return Err("--file and - only work when rolling, with no other expressions".to_string());
+        }
+        Ok(opts)
+    }
+}
+
+/// Picks a seed from OsRng, for rolls that weren't given one, so that any
+/// roll can be reproduced.
+pub(crate) fn random_seed() -> io::Result<u64> {
+    OsRng::new().map(|mut rng| rng.next_u64())
+}
+
+/// Parses the value given to an option.
+fn number<T: FromStr>(option: &str, value: Option<String>) -> Result<T, String> {
+    match value.map(|v| v.parse()) {
+        Some(Ok(n)) => Ok(n),
+        _ => Err(format!("{} needs a whole number", option)),
+    }
+}
+
+/// Runs the command line with the given arguments, not including the program
+/// name, parsing dice with `registry`.
+///
+/// With `stats` as the first argument, prints a table and bar chart of each
+/// expression's distribution, as wide as the terminal or, if it's set,
+/// `COLUMNS`.
+/// With `compare`, prints the distributions of two or more expressions in
+/// columns, followed by how each pair fares against each other.
+///
+/// A distribution that would take too long to work out exactly is estimated
+/// from `--trials` rolls (100,000 by default, 10,000,000 at most) shared
+/// between `--threads` threads (one per core by default). Expressions with
+/// so many dice that this would roll more than `Estimator::MAX_DICE` dice
+/// are estimated from fewer rolls.
+///
+/// `--max-dice` and `--max-sides` change how large a dice term `registry`
+/// accepts.
+///
+/// `--file` rolls each line of a file instead, or of stdin when it's `-` or
+/// the lone argument is `-`. Blank lines and lines starting with `#` are
+/// skipped, each result is labelled with its line number, and a line that
+/// fails doesn't stop the rest.
+///
+/// `6x4d6kh3` rolls `4d6kh3` six times over, as does `--times 6`, each
+/// repetition on its own line. `--sort` puts them highest first and
+/// `--aggregate` follows them with their sum, the totals sorted, and the
+/// lowest and highest.
+///
+/// A roll may be labelled and commented, as in `sword: 1d8+3 # magic`, to
+/// tell it apart from the others. The label goes in front of each result
+/// and the comment after it. A label may be a separate argument, as in
+/// `dice sword: 1d8+3`.
+///
+/// With no expressions to roll, starts an interactive prompt that takes
+/// the same arguments a line at a time; see the `repl` module. That needs
+/// the `repl` feature, which is on by default.
+///
+/// Dice are rolled with a `SeededRng`, from the seed given with `--seed` or
+/// else a random one, which `--verbose` prints.
+///
+/// With `--format json`, prints a single JSON object instead of text, with
+/// the seed (as a string, since not every JSON reader can hold 64 bits) and
+/// a `rolls` array holding an entry for each expression: the argument as
+/// given, its canonical notation, and either its total and every die behind
+/// it, with any label and comment, or the error that stopped it rolling. A
+/// repeated roll has a `repetitions` array of those instead, and an
+/// `aggregate` with `--aggregate`. If any expression can't be parsed nothing
+/// is rolled, and `rolls` holds the parse error of each one that couldn't
+/// be.
+///
+/// Returns the exit code: 0 on success, 1 if an expression couldn't be
+/// parsed or rolled, and 2 for bad options.
+pub fn run<I: Iterator<Item = String>>(args: I, registry: &Registry) -> i32 {
+    let args: Vec<String> = args.collect();
+    let opts = match Options::from_args(args.iter().cloned()) {
+        Ok(opts) => opts,
+        Err(e)   => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    #[cfg(feature = "repl")]
+    {
+        if opts.command == Command::Roll && opts.exprs.is_empty() && opts.file.is_none() {
+            return repl::run(&args, opts.seed, registry);
+        }
+    }
+    execute(opts, registry)
+}
+
+/// Carries out the options, returning the exit code.
+pub(crate) fn execute(opts: Options, registry: &Registry) -> i32 {
+    if opts.command == Command::Compare && opts.exprs.len() < 2 {
+        eprintln!("compare needs at least two expressions");
+        return 2;
+    }
+
+    let mut registry = registry.clone();
+    if let Some(max) = opts.max_dice {
+        registry = registry.max_count(max);
+    }
+    if let Some(max) = opts.max_sides {
+        registry = registry.max_sides(max);
+    }
+
+    // Parse everything up front so a typo is reported before anything is
+    // rolled. Only rolls can be repeated.
+    let mut exprs = Vec::new();
+    let mut errors = Vec::new();
+    let mut failed = false;
+    for arg in &opts.exprs {
+        let parsed = match opts.command {
+            Command::Roll => registry.parse_repeat(arg),
+            _ => registry.parse_expr(arg).map(|expr| Repeat::new(1, expr)),
+        };
+        match parsed {
+            Ok(repeat) if opts.percentile => exprs.push((arg, repeat.percentile())),
+            Ok(repeat) => exprs.push((arg, repeat)),
+            Err(_) if opts.lenient => {}
+            Err(e) if opts.format == Format::Json => {
+                errors.push(parse_error_json(None, arg, e));
+                failed = true;
+            }
+            Err(e)   => {
+                eprintln!("{}", e.render(arg));
+                failed = true;
+            }
+        }
+    }
+
+    let seed = match opts.seed.map_or_else(random_seed, Ok) {
+        Ok(seed) => seed,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    if failed {
+        if opts.format == Format::Json {
+            println!("{}", document(seed, errors));
+        }
+        return 1;
+    }
+    if opts.verbose && opts.format == Format::Text {
+        println!("Seed: {}", seed);
+    }
+
+    if opts.command != Command::Roll {
+        let exprs = exprs.into_iter().map(|(arg, repeat)| (arg, repeat.into_expr())).collect();
+        let mut estimator = Estimator::new(seed).trials(opts.trials);
+        if let Some(threads) = opts.threads {
+            estimator = estimator.threads(threads);
+        }
+        return match opts.command {
+            Command::Stats => stats(exprs, &opts, &estimator),
+            _ => compare(exprs, &estimator),
+        };
+    }
+
+    let mut rng = SeededRng::new(seed);
+
+    if let Some(ref path) = opts.file {
+        return batch(path, &opts, &registry, &mut rng, seed);
+    }
+
+    let mut rolls = Vec::new();
+    for (arg, repeat) in exprs {
+        failed |= !roll(arg, None, &repeat, &opts, &mut rng, &mut rolls);
+    }
+    if opts.format == Format::Json {
+        println!("{}", document(seed, rolls));
+    }
+    if failed { 1 } else { 0 }
+}
+
+/// Rolls each line read from `path`, or from stdin for `-`.
+fn batch(path: &str, opts: &Options, registry: &Registry, rng: &mut SeededRng, seed: u64) -> i32 {
+    let reader: Box<dyn BufRead> = if path == "-" {
+        Box::new(BufReader::new(io::stdin()))
+    } else {
+        match File::open(path) {
+            Ok(file) => Box::new(BufReader::new(file)),
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                return 1;
+            }
+        }
+    };
+
+    let mut failed = false;
+    let mut rolls = Vec::new();
+    for (i, line) in reader.lines().enumerate() {
+        let n = i + 1;
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                eprintln!("{}: {}", path, e);
+                failed = true;
+                break;
+            }
+        };
+        let text = line.trim();
+        if text.is_empty() || text.starts_with('#') {
+            continue;
+        }
+
+        let label = format!("line {}: ", n);
+        let repeat = match registry.parse_repeat(text) {
+            Ok(repeat) if opts.percentile => repeat.percentile(),
+            Ok(repeat) => repeat,
+            Err(_) if opts.lenient => continue,
+            Err(e) => {
+                failed = true;
+                if opts.format == Format::Json {
+                    rolls.push(parse_error_json(Some(n), text, e));
+                } else {
+                    // Keep the carets under the input after the label
+                    let indent = format!("\n{}", " ".repeat(label.len()));
+                    eprintln!("{}{}", label, e.render(text).replace('\n', &indent));
+                }
+                continue;
+            }
+        };
+
+        failed |= !roll(text, Some(n), &repeat, opts, rng, &mut rolls);
+    }
+
+    if opts.format == Format::Json {
+        println!("{}", document(seed, rolls));
+    }
+    if failed { 1 } else { 0 }
+}
+
+/// The whole of the `--format json` output.
+fn document(seed: u64, rolls: Vec<Json>) -> Json {
+    Json::object(vec![("seed", Json::Str(seed.to_string())), ("rolls", Json::Array(rolls))])
+}
+
+/// Describes an expression that couldn't be parsed, for `--format json`.
+fn parse_error_json(line: Option<usize>, arg: &str, e: ParseError) -> Json {
+    let mut fields = Vec::new();
+    if let Some(n) = line {
+        fields.push(("line", Json::Int(n as i64)));
+    }
+    fields.push(("expression", arg.into()));
+    fields.push(("error", e.to_string().as_str().into()));
+    Json::object(fields)
+}
+
+/// Rolls an expression as many times as it and `--times` ask, printing the
+/// results, labelled with the line they were read from if any, or adding
+/// them to `rolls` for `--format json`.
+///
+/// Returns whether every roll worked.
+fn roll(arg: &str, line: Option<usize>, repeat: &Repeat, opts: &Options, rng: &mut SeededRng, rolls: &mut Vec<Json>) -> bool {
+    let times = repeat.times().saturating_mul(opts.times);
+    let mut results: Vec<_> = (0..times).map(|_| repeat.result(&mut *rng)).collect();
+    if opts.lenient {
+        results.retain(Result::is_ok);
+        if results.is_empty() {
+            return true;
+        }
+    }
+    if opts.sort {
+        results.sort_by_key(|result| Reverse(result.as_ref().ok().map(ExprResult::total)));
+    }
+    let totals: Vec<i64> = results.iter().filter_map(|result| result.as_ref().ok()).map(ExprResult::total).collect();
+    let aggregate = if opts.aggregate && times > 1 && !totals.is_empty() { Some(aggregate(&totals)) } else { None };
+    let ok = results.iter().all(Result::is_ok) && !matches!(aggregate, Some(Err(_)));
+
+    if opts.format == Format::Json {
+        let mut fields = Vec::new();
+        if let Some(n) = line {
+            fields.push(("line", Json::Int(n as i64)));
+        }
+        fields.push(("expression", arg.into()));
+        fields.push(("canonical", repeat.to_string().as_str().into()));
+        if times == 1 && repeat.times() == 1 {
+            fields.extend(result_json(results.remove(0), opts.ladder));
+        } else {
+            let results = results.into_iter().map(|result| Json::object(result_json(result, opts.ladder)));
+            fields.push(("times", Json::Int(i64::from(times))));
+            fields.push(("repetitions", Json::Array(results.collect())));
+        }
+        match aggregate {
+            Some(Ok((sum, sorted))) => fields.push(("aggregate", Json::object(vec![
+                ("sum", Json::Int(sum)),
+                ("sorted", Json::ints(&sorted)),
+                ("min", Json::Int(sorted[sorted.len() - 1])),
+                ("max", Json::Int(sorted[0])),
+            ]))),
+            Some(Err(e)) => fields.push(("aggregate", Json::object(vec![("error", e.to_string().as_str().into())]))),
+            None => {}
+        }
+        rolls.push(Json::object(fields));
+        return ok;
+    }
+
+    let label = line.map_or_else(String::new, |n| format!("line {}: ", n));
+    let comment = repeat.comment().map_or_else(String::new, |comment| format!(" # {}", comment));
+    for result in results {
+        match result {
+            Ok(roll) if opts.ladder => println!("{}{} => {}{}", label, roll, Ladder(roll.total()), comment),
+            Ok(roll) => println!("{}{}{}", label, roll, comment),
+            Err(e) => eprintln!("{}{}: {}", label, arg, e),
+        }
+    }
+    match aggregate {
+        Some(Ok((sum, sorted))) => {
+            let list: Vec<_> = sorted.iter().map(i64::to_string).collect();
+            let (min, max) = (sorted[sorted.len() - 1], sorted[0]);
+            let name = repeat.label().map_or_else(String::new, |name| format!("{}: ", name));
+            println!("{}{}Sum: {}, Sorted: [{}], Min: {}, Max: {}{}", label, name, sum, list.join(", "), min, max, comment);
+        }
+        Some(Err(e)) => eprintln!("{}{}: {}", label, arg, e),
+        None => {}
+    }
+    ok
+}
+
+/// The sum of the totals and the totals from highest to lowest, for
+/// `--aggregate`.
+fn aggregate(totals: &[i64]) -> Result<(i64, Vec<i64>), EvalError> {
+    let sum = totals.iter().try_fold(0i64, |sum, &total| sum.checked_add(total)).ok_or(EvalError::Overflow)?;
+    let mut sorted = totals.to_vec();
+    sorted.sort_by_key(|&total| Reverse(total));
+    Ok((sum, sorted))
+}
+
+/// Describes the outcome of one roll for `--format json`.
+fn result_json(result: Result<ExprResult, EvalError>, ladder: bool) -> Vec<(&'static str, Json)> {
+    let mut fields = Vec::new();
+    match result {
+        Ok(roll) => {
+            fields.push(("total", Json::Int(roll.total())));
+            if ladder {
+                fields.push(("ladder", Ladder(roll.total()).to_string().as_str().into()));
+            }
+            fields.push(("result", roll.to_json()));
+        }
+        Err(e) => fields.push(("error", e.to_string().as_str().into())),
+    }
+    fields
+}
+
+/// How wide to draw charts: `COLUMNS` if it's set, or else the width of the
+/// terminal on stdout, or else `DEFAULT_WIDTH` when that isn't a terminal.
+fn chart_width() -> usize {
+    let columns = env::var("COLUMNS").ok().and_then(|c| c.parse().ok()).filter(|&w| w > 0);
+    let terminal = || terminal_size::terminal_size().map(|(Width(w), _)| usize::from(w)).filter(|&w| w > 0);
+    columns.or_else(terminal).unwrap_or(DEFAULT_WIDTH)
+}
+
+/// Prints the distribution of each expression.
+fn stats(exprs: Vec<(&String, Expr)>, opts: &Options, estimator: &Estimator) -> i32 {
+    let width = chart_width();
+    let mut failed = false;
+    let mut first = true;
+    for (arg, expr) in exprs {
+        match estimator.odds(&expr) {
+            Ok(odds) => {
+                if !first {
+                    println!();
+                }
+                first = false;
+                let zero = match odds {
+                    Odds::Exact(_) => expr.division_by_zero().and_then(|p| p.to_f64()).unwrap_or(0.0),
+                    Odds::Estimated(ref estimate) => estimate.division_by_zero(),
+                };
+                print!("{}", stats_table(&expr.notation(), &odds, zero, width));
+            }
+            Err(_) if opts.lenient => {}
+            Err(e) => {
+                eprintln!("{}: {}", arg, e);
+                failed = true;
+            }
+        }
+    }
+    if failed { 1 } else { 0 }
+}
+
+/// Renders a summary of the distribution and how likely a roll is to
+/// divide by zero, if it can, then a row for every total with its chance,
+/// the chance of it or more and of it or less, and a bar filling what's
+/// left of `width`.
+fn stats_table(notation: &str, odds: &Odds, zero: f64, width: usize) -> String {
+    let mut out = String::new();
+    writeln!(out, "{}", notation).unwrap();
+    let rows = match *odds {
+        Odds::Exact(ref dist) => {
+            writeln!(
+                out,
+                "Mean: {}, SD: {:.4}, Variance: {}, Min: {}, Max: {}, Mode: {}",
+                exact(&dist.mean()), dist.std_dev(), exact(&dist.variance()), dist.min(), dist.max(), dist.mode()
+            ).unwrap();
+            exact_rows(dist)
+        }
+        Odds::Estimated(ref estimate) => {
+            let (lo, hi) = estimate.mean_interval();
+            let (_, margin) = estimate.interval(0.5);
+            writeln!(
+                out,
+                "Estimated from {} rolls, chances within {:.2}% at 95% confidence",
+                estimate.trials(), (margin - 0.5) * 100.0
+            ).unwrap();
+            writeln!(
+                out,
+                "Mean: {:.4} ({:.4} to {:.4}), SD: {:.4}, Min: {}, Max: {}, Mode: {}",
+                estimate.mean(), lo, hi, estimate.std_dev(), estimate.min(), estimate.max(), estimate.mode()
+            ).unwrap();
+            estimated_rows(estimate)
+        }
+    };
+    if zero > 0.0 {
+        writeln!(out, "P(division by zero): {}, left out of the chances below", percent(zero)).unwrap();
+    }
+
+    let digits = |v: i64| v.to_string().len();
+    let vw = digits(odds.min()).max(digits(odds.max())).max("Value".len());
+    // The value, three percentages and the gaps between them
+    let bar_width = width.saturating_sub(vw + 3 * 9 + 2).max(10);
+    let longest = rows.iter().map(|r| r.1).fold(0.0, f64::max);
+
+    writeln!(out, "{:>vw$}  {:>7}  {:>7}  {:>7}", "Value", "Chance", "P(>=)", "P(<=)", vw = vw).unwrap();
+    for (v, p, at_least, at_most) in rows {
+        let bar = p / longest * bar_width as f64;
+        let row = format!(
+            "{:>vw$}  {:>7}  {:>7}  {:>7}  {}",
+            v, percent(p), percent(at_least), percent(at_most), "#".repeat(bar.round() as usize), vw = vw
+        );
+        writeln!(out, "{}", row.trim_end()).unwrap();
+    }
+    out
+}
+
+/// Each value with its chance, the chance of it or more and of it or less,
+/// worked out exactly before they're rounded.
+fn exact_rows(dist: &Distribution) -> Vec<(i64, f64, f64, f64)> {
+    let f = |r: &BigRational| r.to_f64().unwrap_or(0.0);
+    let (mut below, mut total) = (BigRational::zero(), BigRational::one());
+    let mut rows = Vec::new();
+    for (v, p) in dist.iter() {
+        below += &p;
+        rows.push((v, f(&p), f(&total), f(&below)));
+        total -= &p;
+    }
+    rows
+}
+
+/// Like `exact_rows`, counting rolls.
+fn estimated_rows(estimate: &Estimate) -> Vec<(i64, f64, f64, f64)> {
+    let n = estimate.trials() as f64;
+    let (mut below, mut total) = (0, estimate.trials());
+    let mut rows = Vec::new();
+    for &(v, count) in estimate.counts() {
+        below += count;
+        rows.push((v, count as f64 / n, total as f64 / n, below as f64 / n));
+        total -= count;
+    }
+    rows
+}
+
+/// Prints the distributions side by side.
+fn compare(exprs: Vec<(&String, Expr)>, estimator: &Estimator) -> i32 {
+    let mut odds = Vec::new();
+    for (arg, expr) in exprs {
+        match estimator.odds(&expr) {
+            Ok(o) => odds.push((expr.notation(), o)),
+            Err(e) => {
+                eprintln!("{}: {}", arg, e);
+                return 1;
+            }
+        }
+    }
+    print!("{}", compare_table(&odds));
+    0
+}
+
+/// Renders a column for each distribution: summary statistics, the chance
+/// of every total and a few percentiles, noting which is highest at each.
+/// Then for each pair, the chance of either coming out ahead and by how
+/// much on average.
+fn compare_table(odds: &[(String, Odds)]) -> String {
+    let mut out = String::new();
+    let cw = odds.iter().map(|(name, _)| name.len()).max().unwrap_or(0).max(8);
+    let row = |out: &mut String, label: &str, cells: Vec<String>| {
+        let mut line = format!("{:<10}", label);
+        for cell in cells {
+            line.push_str(&format!("  {:>cw$}", cell, cw = cw));
+        }
+        writeln!(out, "{}", line.trim_end()).unwrap();
+    };
+    let each = |f: &dyn Fn(&Odds) -> String| odds.iter().map(|(_, o)| f(o)).collect();
+
+    row(&mut out, "", odds.iter().map(|(name, _)| name.clone()).collect());
+    row(&mut out, "Mean", each(&|o| format!("{:.4}", o.mean())));
+    row(&mut out, "SD", each(&|o| format!("{:.4}", o.std_dev())));
+    row(&mut out, "Min", each(&|o| o.min().to_string()));
+    row(&mut out, "Max", each(&|o| o.max().to_string()));
+    row(&mut out, "Mode", each(&|o| o.mode().to_string()));
+    if odds.iter().any(|(_, o)| !o.is_exact()) {
+        row(&mut out, "Rolls", each(&|o| match *o {
+            Odds::Exact(_) => "exact".to_string(),
+            Odds::Estimated(ref estimate) => estimate.trials().to_string(),
+        }));
+    }
+
+    writeln!(out).unwrap();
+    let chances: Vec<Vec<(i64, f64)>> = odds.iter().map(|(_, o)| o.chances()).collect();
+    let mut values: Vec<i64> = chances.iter().flat_map(|c| c.iter().map(|&(v, _)| v)).collect();
+    values.sort();
+    values.dedup();
+    row(&mut out, "Value", Vec::new());
+    for v in values {
+        let cells = chances.iter().map(|c| match c.binary_search_by_key(&v, |&(v, _)| v) {
+            Ok(i) => percent(c[i].1),
+            Err(_) => "-".to_string(),
+        });
+        row(&mut out, &v.to_string(), cells.collect());
+    }
+
+    writeln!(out).unwrap();
+    let mut header = odds.iter().map(|(name, _)| name.clone()).collect::<Vec<_>>();
+    header.push("Highest".to_string());
+    row(&mut out, "Percentile", header);
+    for &pct in &PERCENTILES {
+        let values: Vec<i64> = odds.iter().map(|(_, o)| o.percentile(pct)).collect();
+        let best = values.iter().cloned().max().unwrap_or(0);
+        let highest: Vec<&str> = odds.iter().zip(&values).filter(|&(_, &v)| v == best).map(|((name, _), _)| name.as_str()).collect();
+        let mut cells: Vec<String> = values.iter().map(|v| v.to_string()).collect();
+        cells.push(if highest.len() == odds.len() { "tie".to_string() } else { highest.join(", ") });
+        row(&mut out, &format!("{}%", pct), cells);
+    }
+
+    for (i, (a, oa)) in odds.iter().enumerate() {
+        for (j, (b, ob)) in odds.iter().enumerate().skip(i + 1) {
+            writeln!(out).unwrap();
+            let diff = match (oa, ob) {
+                (Odds::Exact(da), Odds::Exact(db)) => da.difference(db),
+                _ => None,
+            };
+            if let Some(diff) = diff {
+                writeln!(out, "{} vs {}", a, b).unwrap();
+                writeln!(out, "P({} > {}): {}", a, b, percent(diff.at_least(1).to_f64().unwrap_or(0.0))).unwrap();
+                writeln!(out, "P({} = {}): {}", a, b, percent(diff.chance(0).to_f64().unwrap_or(0.0))).unwrap();
+                writeln!(out, "P({} < {}): {}", a, b, percent(diff.at_most(-1).to_f64().unwrap_or(0.0))).unwrap();
+                writeln!(out, "Expected difference: {}", exact(&diff.mean())).unwrap();
+            } else {
+                let (less, equal) = head_to_head(&chances[i], &chances[j]);
+                writeln!(out, "{} vs {} (estimated)", a, b).unwrap();
+                writeln!(out, "P({} > {}): {}", a, b, percent((1.0 - less - equal).max(0.0))).unwrap();
+                writeln!(out, "P({} = {}): {}", a, b, percent(equal)).unwrap();
+                writeln!(out, "P({} < {}): {}", a, b, percent(less)).unwrap();
+                writeln!(out, "Expected difference: {:.4}", oa.mean() - ob.mean()).unwrap();
+            }
+        }
+    }
+    out
+}
+
+/// The chances that a value from `a` is less than, and equal to, an
+/// independent value from `b`, both given in increasing order.
+fn head_to_head(a: &[(i64, f64)], b: &[(i64, f64)]) -> (f64, f64) {
+    let (mut less, mut equal) = (0.0, 0.0);
+    let (mut i, mut below) = (0, 0.0);
+    for &(v, q) in b {
+        while i < a.len() && a[i].0 < v {
+            below += a[i].1;
+            i += 1;
+        }
+        less += q * below;
+        if i < a.len() && a[i].0 == v {
+            equal += q * a[i].1;
+        }
+    }
+    (less, equal)
+}
+
+/// A chance as a percentage, never rounding a chance that could go either
+/// way to 0% or 100%.
+fn percent(p: f64) -> String {
+    let pct = p * 100.0;
+    if p > 0.0 && pct < 0.005 {
+        "<0.01%".to_string()
+    } else if p < 1.0 && pct > 99.995 {
+        ">99.99%".to_string()
+    } else {
+        format!("{:.2}%", pct)
+    }
+}
+
+/// A fraction as a decimal, followed by the fraction itself when it isn't
+/// a whole number and is short enough to read.
+fn exact(r: &BigRational) -> String {
+    let fraction = r.to_string();
+    if r.is_integer() {
+        fraction
+    } else if fraction.len() <= 20 {
+        format!("{:.4} ({})", r.to_f64().unwrap_or(f64::NAN), fraction)
+    } else {
+        format!("{:.4}", r.to_f64().unwrap_or(f64::NAN))
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Result<Options, String> {
+        Options::from_args(args.iter().map(|a| a.to_string()))
+    }
+
+    #[test]
+    fn files_stand_in_for_expressions() {
+        assert_eq!(options(&["-"]).unwrap().file, Some("-".to_string()));
+        assert_eq!(options(&["--ladder", "--file", "rolls.txt"]).unwrap().file, Some("rolls.txt".to_string()));
+        assert!(options(&["--file"]).is_err());
+        assert!(options(&["1d6", "-"]).is_err());
+        assert!(options(&["stats", "--file", "rolls.txt"]).is_err());
+    }
+
+    #[test]
+    fn parse_errors_are_reported_as_json() {
+        let e = Registry::new().parse_expr("2d").unwrap_err();
+        assert_eq!(parse_error_json(None, "2d", e).to_string(), r#"{"expression":"2d","error":"Expected number of sides after 'd'"}"#);
+        assert_eq!(parse_error_json(Some(3), "2d", e).to_string(), r#"{"line":3,"expression":"2d","error":"Expected number of sides after 'd'"}"#);
+    }
+
+    #[test]
+    fn bad_arguments_fail_the_run() {
+        let run = |args: &[&str]| execute(options(args).unwrap(), &Registry::new());
+        assert_eq!(run(&["--seed", "1", "2d6", "3x8"]), 1);
+        assert_eq!(run(&["--seed", "1", "0x2d6"]), 1);
+        assert_eq!(run(&["--seed", "1", "--lenient", "2d6", "3x8"]), 0);
+    }
+
+    #[test]
+    fn rolls_repeat_with_times() {
+        let opts = options(&["--times", "6", "--sort", "--aggregate", "4d6kh3"]).unwrap();
+        assert_eq!((opts.times, opts.sort, opts.aggregate), (6, true, true));
+        assert!(options(&["--times", "0", "1d6"]).is_err());
+        assert!(options(&["stats", "--times", "2", "1d6"]).is_err());
+        assert!(options(&["stats", "--trials", "18446744073709551615", "1d6"]).is_err());
+        assert_eq!(aggregate(&[3, 18, 11]), Ok((32, vec![18, 11, 3])));
+        assert_eq!(aggregate(&[i64::MAX, 1]), Err(EvalError::Overflow));
+    }
+
+    #[test]
+    fn labels_join_their_expressions() {
+        let opts = options(&["sword:", "1d8+3", "Perception: 1d20+5", "2d6"]).unwrap();
+        assert_eq!(opts.exprs, ["sword: 1d8+3", "Perception: 1d20+5", "2d6"]);
+    }
+
+    #[test]
+    fn stats_tables_show_every_total() {
+        let dist = "2d2".parse::<Expr>().unwrap().distribution().unwrap();
+        let table = stats_table("2d2", &Odds::Exact(dist), 0.0, 50);
+        let expected = "\
+2d2
+Mean: 3, SD: 0.7071, Variance: 0.5000 (1/2), Min: 2, Max: 4, Mode: 3
+Value   Chance    P(>=)    P(<=)
+    2   25.00%  100.00%   25.00%  ########
+    3   50.00%   75.00%   75.00%  ################
+    4   25.00%   25.00%  100.00%  ########
+";
+        assert_eq!(table, expected);
+    }
+
+    #[test]
+    fn stats_tables_show_the_chance_of_dividing_by_zero() {
+        let expr: Expr = "1d6/(1d2-1)".parse().unwrap();
+        let zero = expr.division_by_zero().unwrap().to_f64().unwrap();
+        let table = stats_table("1d6/(1d2-1)", &Odds::Exact(expr.distribution().unwrap()), zero, 50);
+        assert!(table.lines().nth(1).unwrap().starts_with("Mean: 3.5000 (7/2),"));
+        assert_eq!(table.lines().nth(2), Some("P(division by zero): 50.00%, left out of the chances below"));
+        assert!(!stats_table("2d2", &Odds::Exact(Distribution::constant(1)), 0.0, 50).contains("division"));
+    }
+
+    #[test]
+    fn compare_tables_line_up() {
+        let dist = |s: &str| (s.to_string(), Odds::Exact(s.parse::<Expr>().unwrap().distribution().unwrap()));
+        let table = compare_table(&[dist("1d2"), dist("1d3-1")]);
+        let expected = "                 1d2     1d3-1
+Mean          1.5000    1.0000
+SD            0.5000    0.8165
+Min                1         0
+Max                2         2
+Mode               1         0
+
+Value
+0                  -    33.33%
+1             50.00%    33.33%
+2             50.00%    33.33%
+
+Percentile       1d2     1d3-1   Highest
+5%                 1         0       1d2
+10%                1         0       1d2
+25%                1         0       1d2
+50%                1         1       tie
+75%                2         2       tie
+90%                2         2       tie
+95%                2         2       tie
+
+1d2 vs 1d3-1
+P(1d2 > 1d3-1): 50.00%
+P(1d2 = 1d3-1): 33.33%
+P(1d2 < 1d3-1): 16.67%
+Expected difference: 0.5000 (1/2)
+";
+        assert_eq!(table, expected);
+    }
+
+    #[test]
+    fn compare_tables_estimate_differences_that_overflow() {
+        let dist = |s: &str| (s.to_string(), Odds::Exact(s.parse::<Expr>().unwrap().distribution().unwrap()));
+        let table = compare_table(&[dist("d{4611686018427387904}"), dist("d{-4611686018427387905}")]);
+        assert!(table.contains("d{4611686018427387904} vs d{-4611686018427387905} (estimated)"));
+        assert!(table.contains("P(d{4611686018427387904} > d{-4611686018427387905}): 100.00%"));
+    }
+
+    #[test]
+    fn tiny_chances_arent_rounded_away() {
+        let dist = "1d100000".parse::<Expr>().unwrap().distribution().unwrap();
+        let f = |r: BigRational| r.to_f64().unwrap();
+        assert_eq!(percent(f(dist.chance(1))), "<0.01%");
+        assert_eq!(percent(f(dist.at_least(2))), ">99.99%");
+        assert_eq!(percent(f(dist.at_least(1))), "100.00%");
+    }
+}
@@ -0,0 +1,432 @@
+//! The kinds of dice a `RollCmd` can roll.
+
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use num_bigint::BigUint;
+use num_traits::{self, ToPrimitive};
+
+use distribution::Distribution;
+use rng::DiceRng;
+use rollable::Rollable;
+
+/// A single die.
+///
+/// Every die is rolled through a `DiceRng`: the rng picks one of `sides()`
+/// faces and the die decides what that face is worth.
+///
+/// # Examples
+///
+/// ```
+/// use rcmd::{Die, MaxRng, RollCmd};
+/// let fibonacci = Die::Faces(vec![1, 1, 2, 3, 5, 8]);
+/// let result = RollCmd::from_die(2, fibonacci).result(&mut MaxRng);
+/// assert!(result.total() == 16);
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Die {
+    /// A die numbered 1 to n (`dn`)
+    Standard(u32),
+    /// A FATE die with two each of -1, 0 and +1 (`dF` or `dF.2`)
+    Fudge,
+    /// A FATE die with one -1, one +1 and four blanks (`dF.1`)
+    FudgeOne,
+    /// A d100 rolled as a tens die and a units die, with that many extra
+    /// tens dice keeping the best (`d%b1`) or, when negative, the worst
+    /// (`d%p1`)
+    Percentile(i64),
+    /// A die with the listed faces, each equally likely (`d{1,1,2,3,5,8}`)
+    Faces(Vec<i64>),
+    /// A die with named faces, which are tallied rather than summed
+    /// (`d{hit,hit,miss,crit}`)
+    Symbols(Vec<String>),
+    /// A die defined outside this library (`dname`, see `Registry`)
+    Custom(CustomDie),
+}
+
+impl Die {
+    /// The number of faces the rng chooses between. Custom dice roll
+    /// themselves, so report 0.
+    pub fn sides(&self) -> u32 {
+        match *self {
+            Die::Standard(sides) => sides,
+            Die::Fudge => 3,
+            Die::FudgeOne => 6,
+            Die::Percentile(_) => 100,
+            Die::Faces(ref faces) => faces.len() as u32,
+            Die::Symbols(ref faces) => faces.len() as u32,
+            Die::Custom(_) => 0,
+        }
+    }
+
+    /// The value of the nth face, counting from 1. Named faces and custom
+    /// dice have no numbered faces and count as 0.
+    pub fn face(&self, n: u32) -> i64 {
+        match *self {
+            Die::Faces(ref faces) => faces[n as usize - 1],
+            Die::Symbols(_) | Die::Custom(_) => 0,
+            Die::Standard(_) | Die::Percentile(_) => i64::from(n),
+            Die::Fudge => i64::from(n) - 2,
+            Die::FudgeOne => match n {
+                1 => -1,
+                6 => 1,
+                _ => 0,
+            },
+        }
+    }
+
+    /// Whether this is one of the FATE dice.
+    pub fn is_fudge(&self) -> bool {
+        *self == Die::Fudge || *self == Die::FudgeOne
+    }
+
+    /// Whether this is a d100 rolled as tens and units dice.
+    pub fn is_percentile(&self) -> bool {
+        matches!(*self, Die::Percentile(_))
+    }
+
+    /// The name of the nth face, counting from 1, if the faces are named.
+    pub fn symbol(&self, n: u32) -> Option<&str> {
+        match *self {
+            Die::Symbols(ref faces) => Some(&faces[n as usize - 1]),
+            _ => None,
+        }
+    }
+
+    /// Whether the faces are named rather than numbered.
+    pub fn is_symbolic(&self) -> bool {
+        matches!(*self, Die::Symbols(_))
+    }
+
+    /// Whether the die has no faces to land on, like `Die::Standard(0)` or
+    /// `Die::Faces(vec![])` or a custom die made from one, and so can't be
+    /// rolled.
+    pub fn is_empty(&self) -> bool {
+        match *self {
+            Die::Custom(ref custom) => custom.die.is_empty(),
+            _ => self.sides() == 0,
+        }
+    }
+
+    /// Rolls the die once, also returning the tens dice that bonus or
+    /// penalty dice discarded.
+    ///
+    /// A percentile die asks the rng for each tens die and then the units
+    /// die, each as a d10 read from 0 to 9. `00` and `0` make 100.
+    pub(crate) fn roll_tens<R: DiceRng + ?Sized>(&self, rng: &mut R) -> (i64, Vec<i64>) {
+        let extra = match *self {
+            Die::Percentile(extra) => extra,
+            Die::Custom(ref custom) => return (custom.die.roll(&mut &mut *rng), Vec::new()),
+            _ => return (self.face(rng.roll_die(self.sides())), Vec::new()),
+        };
+
+        let mut tens: Vec<i64> = (0..=extra.unsigned_abs()).map(|_| (i64::from(rng.roll_die(10)) - 1) * 10).collect();
+        let units = i64::from(rng.roll_die(10)) - 1;
+        let value = |tens: i64| if tens + units == 0 { 100 } else { tens + units };
+        let best = if extra < 0 {
+            tens.iter().enumerate().max_by_key(|&(_, &t)| value(t))
+        } else {
+            tens.iter().enumerate().min_by_key(|&(_, &t)| value(t))
+        };
+        let (i, _) = best.expect("at least one tens die");
+        let kept = tens.remove(i);
+        (value(kept), tens)
+    }
+}
+
+impl fmt::Display for Die {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Die::Standard(sides) => write!(f, "d{}", sides),
+            Die::Fudge => write!(f, "dF"),
+            Die::FudgeOne => write!(f, "dF.1"),
+            Die::Percentile(extra) if extra < 0 => write!(f, "d%p{}", -extra),
+            Die::Percentile(extra) => write!(f, "d%b{}", extra),
+            Die::Faces(ref faces) => {
+                let faces: Vec<_> = faces.iter().map(|n| n.to_string()).collect();
+                write!(f, "d{{{}}}", faces.join(","))
+            }
+            Die::Symbols(ref faces) => write!(f, "d{{{}}}", faces.join(",")),
+            Die::Custom(ref custom) => write!(f, "d{}", custom.name),
+        }
+    }
+}
+
+/// An error raised building a `RollCmd` around a die that can't be rolled.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DieError {
+    /// A die with no faces to land on, e.g. `Die::Standard(0)`.
+    ZeroSides,
+}
+
+impl fmt::Display for DieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            DieError::ZeroSides => write!(f, "Dice must have at least one side"),
+        }
+    }
+}
+
+impl Error for DieError {}
+
+impl Rollable for Die {
+    type Output = i64;
+
+    fn roll(&self, rng: &mut dyn DiceRng) -> i64 {
+        self.roll_tens(rng).0
+    }
+
+    fn min(&self) -> i64 {
+        match *self {
+            Die::Standard(_) | Die::Percentile(_) => 1,
+            Die::Fudge | Die::FudgeOne => -1,
+            Die::Faces(ref faces) => faces.iter().cloned().min().unwrap_or(0),
+            Die::Symbols(_) => 0,
+            Die::Custom(ref custom) => custom.die.min(),
+        }
+    }
+
+    fn max(&self) -> i64 {
+        match *self {
+            Die::Standard(sides) => i64::from(sides),
+            Die::Percentile(_) => 100,
+            Die::Fudge | Die::FudgeOne => 1,
+            Die::Faces(ref faces) => faces.iter().cloned().max().unwrap_or(0),
+            Die::Symbols(_) => 0,
+            Die::Custom(ref custom) => custom.die.max(),
+        }
+    }
+
+    fn expected(&self) -> f64 {
+        match *self {
+            Die::Custom(ref custom) => custom.die.expected(),
+            Die::Standard(sides) => (f64::from(sides) + 1.0) / 2.0,
+            _ => self.distribution().map_or(0.0, |dist| dist.mean().to_f64().unwrap_or(0.0)),
+        }
+    }
+
+    fn notation(&self) -> String {
+        self.to_string()
+    }
+
+    fn is_empty(&self) -> bool {
+        Die::is_empty(self)
+    }
+
+    /// Known for every die but a custom one that doesn't report its own, or
+    /// one without any faces.
+    fn distribution(&self) -> Option<Distribution> {
+        let dist = match *self {
+            Die::Standard(0) => return None,
+            Die::Faces(ref faces) if faces.is_empty() => return None,
+            Die::Standard(sides) => Distribution::uniform(1..=i64::from(sides)),
+            Die::Fudge => Distribution::uniform(vec![-1, 0, 1]),
+            Die::FudgeOne => Distribution::uniform(vec![-1, 0, 0, 0, 0, 1]),
+            Die::Percentile(extra) => percentile_distribution(extra),
+            Die::Faces(ref faces) => Distribution::uniform(faces.iter().cloned()),
+            Die::Symbols(_) => Distribution::constant(0),
+            Die::Custom(ref custom) => return custom.die.distribution(),
+        };
+        Some(dist)
+    }
+}
+
+/// Works out a percentile die's distribution one units die at a time: the
+/// best (or worst) of k tens dice is at least the ith lowest value with
+/// chance ((10 - i) / 10)^k.
+fn percentile_distribution(extra: i64) -> Distribution {
+    let k = extra.unsigned_abs() as usize + 1;
+    let pow = |i: usize| num_traits::pow(BigUint::from(i), k);
+    let mut weights = Vec::new();
+    for units in 0..10 {
+        let mut values: Vec<i64> = (0..10).map(|t| if t == 0 && units == 0 { 100 } else { t * 10 + units }).collect();
+        values.sort();
+        for (i, v) in values.into_iter().enumerate() {
+            let w = if extra < 0 { pow(i + 1) - pow(i) } else { pow(10 - i) - pow(9 - i) };
+            weights.push((v, w));
+        }
+    }
+    Distribution::weighted(weights)
+}
+
+/// A die type from outside this library, known to the parser by name.
+///
+/// Custom dice are compared by name.
+///
+/// # Examples
+///
+/// ```
+/// use rcmd::{CustomDie, Die, Registry, RollCmd};
+/// // A d6 numbered 0 to 5
+/// let d6z = CustomDie::new("Z", Die::Faces(vec![0, 1, 2, 3, 4, 5]));
+/// let registry = Registry::new().with(d6z.clone());
+/// let cmd = registry.parse_roll("3dZ").unwrap();
+/// assert!(cmd == RollCmd::from_die(3, Die::Custom(d6z)));
+/// ```
+#[derive(Clone)]
+pub struct CustomDie {
+    name: String,
+    die: Arc<dyn Rollable<Output = i64> + Send + Sync>,
+}
+
+impl CustomDie {
+    /// Name a die so the parser can find it.
+    ///
+    /// # Panics
+    ///
+    /// If the name is empty or contains anything but letters.
+    pub fn new<R: Rollable<Output = i64> + Send + Sync + 'static>(name: &str, die: R) -> CustomDie {
+        assert!(
+            !name.is_empty() && name.chars().all(|c| c.is_alphabetic()),
+            "custom die names must be letters, not {:?}",
+            name
+        );
+        CustomDie { name: name.to_string(), die: Arc::new(die) }
+    }
+
+    /// The name the die is written with after a `d`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl fmt::Debug for CustomDie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CustomDie({:?})", self.name)
+    }
+}
+
+impl PartialEq for CustomDie {
+    fn eq(&self, other: &CustomDie) -> bool {
+        self.name == other.name
+    }
+}
+
+impl Eq for CustomDie {}
+
+/// A result described on the FATE adjective ladder, e.g. `Good (+3)`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Ladder(pub i64);
+
+impl Ladder {
+    /// The adjective for this result. Results off either end of the ladder
+    /// have none.
+    pub fn adjective(&self) -> Option<&'static str> {
+        let name = match self.0 {
+            8 => "Legendary",
+            7 => "Epic",
+            6 => "Fantastic",
+            5 => "Superb",
+            4 => "Great",
+            3 => "Good",
+            2 => "Fair",
+            1 => "Average",
+            0 => "Mediocre",
+            -1 => "Poor",
+            -2 => "Terrible",
+            _ => return None,
+        };
+        Some(name)
+    }
+}
+
+impl fmt::Display for Ladder {
+    /// # Examples
+    /// ```
+    /// use rcmd::Ladder;
+    /// assert!(Ladder(3).to_string() == "Good (+3)");
+    /// assert!(Ladder(0).to_string() == "Mediocre (+0)");
+    /// assert!(Ladder(9).to_string() == "Beyond Legendary (+9)");
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.adjective() {
+            Some(name) => write!(f, "{} ({:+})", name, self.0),
+            None if self.0 > 0 => write!(f, "Beyond Legendary ({:+})", self.0),
+            None => write!(f, "Beyond Terrible ({:+})", self.0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod die_tests {
+    use super::*;
+    use rng::{Fixed, MaxRng, MinRng};
+
+    #[test]
+    fn fudge_dice_have_signed_faces() {
+        let faces: Vec<_> = (1..4).map(|n| Die::Fudge.face(n)).collect();
+        assert_eq!(faces, [-1, 0, 1]);
+
+        let faces: Vec<_> = (1..7).map(|n| Die::FudgeOne.face(n)).collect();
+        assert_eq!(faces, [-1, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn standard_dice_count_from_one() {
+        let die = Die::Standard(20);
+        assert_eq!((die.min(), die.max()), (1, 20));
+        assert_eq!(die.roll(&mut MaxRng), 20);
+    }
+
+    #[test]
+    fn custom_dice_use_their_faces() {
+        let die = Die::Faces(vec![2, 4, 6, 8]);
+        assert_eq!((die.sides(), die.min(), die.max()), (4, 2, 8));
+        assert_eq!(die.roll(&mut Fixed::new(vec![3])), 6);
+        assert_eq!(die.to_string(), "d{2,4,6,8}");
+
+        let die = Die::Symbols(vec!["hit".to_string(), "miss".to_string()]);
+        assert_eq!(die.symbol(2), Some("miss"));
+        assert_eq!(die.to_string(), "d{hit,miss}");
+    }
+
+    #[test]
+    fn distributions_are_known_for_builtin_dice() {
+        assert_eq!(Die::Fudge.expected(), 0.0);
+        assert_eq!(Die::Standard(6).expected(), 3.5);
+        assert!((Die::Percentile(0).expected() - 50.5).abs() < 1e-9);
+
+        let dist = Die::Percentile(2).distribution().unwrap();
+        assert_eq!(dist.chance(1).to_string(), "271/10000");
+        assert_eq!(dist.chance(100).to_string(), "1/10000");
+        assert!(Die::Percentile(1).expected() < Die::Percentile(0).expected());
+        assert!(Die::Percentile(-1).expected() > Die::Percentile(0).expected());
+    }
+
+    #[test]
+    fn custom_dice_roll_themselves() {
+        let die = Die::Custom(CustomDie::new("Z", Die::Faces(vec![0, 1, 2])));
+        assert_eq!(die.roll(&mut MaxRng), 2);
+        assert_eq!((die.min(), die.max()), (0, 2));
+        assert_eq!(die.to_string(), "dZ");
+
+        assert!(Die::Custom(CustomDie::new("Nil", Die::Faces(vec![]))).is_empty());
+        assert!(!die.is_empty());
+    }
+
+    #[test]
+    #[should_panic]
+    fn custom_dice_need_a_name() {
+        CustomDie::new("2", Die::Fudge);
+    }
+
+    #[test]
+    fn percentile_dice_roll_tens_then_units() {
+        assert_eq!(Die::Percentile(0).roll_tens(&mut Fixed::new(vec![5, 8])), (47, vec![]));
+
+        // 00 and 0 is 100, not 0
+        assert_eq!(Die::Percentile(0).roll(&mut MinRng), 100);
+    }
+
+    #[test]
+    fn bonus_and_penalty_dice_pick_a_tens_die() {
+        let rolls = vec![8, 5, 1, 8];
+
+        let tens = Die::Percentile(2).roll_tens(&mut Fixed::new(rolls.clone()));
+        assert_eq!(tens, (7, vec![70, 40]));
+
+        let tens = Die::Percentile(-2).roll_tens(&mut Fixed::new(rolls));
+        assert_eq!(tens, (77, vec![40, 0]));
+    }
+}
@@ -0,0 +1,359 @@
+//! Exact probability distributions of dice totals.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use num_bigint::{BigInt, BigUint};
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, ToPrimitive, Zero};
+
+/// The exact chance of every total a roll can come to.
+///
+/// Chances are kept as whole number weights over a common total, so nothing
+/// is lost to rounding however many dice are combined.
+///
+/// # Examples
+///
+/// ```
+/// use rcmd::{Rollable, RollCmd};
+/// let dist = "4d6kh3".parse::<RollCmd>().unwrap().distribution().unwrap();
+/// assert!(dist.at_least(15).to_string() == "25/108");
+/// assert!(dist.mean().to_string() == "15869/1296");
+/// assert!((dist.min(), dist.max(), dist.mode()) == (3, 18, 13));
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Distribution {
+    /// Every possible value in increasing order, with a weight that isn't 0
+    weights: Vec<(i64, BigUint)>,
+    /// The sum of the weights
+    total: BigUint,
+}
+
+impl Distribution {
+    /// A value that's certain.
+    pub fn constant(value: i64) -> Distribution {
+        Distribution { weights: vec![(value, BigUint::one())], total: BigUint::one() }
+    }
+
+    /// Each value equally likely, counting a repeated value once per time it
+    /// appears, like the faces of a die.
+    ///
+    /// # Panics
+    ///
+    /// If there are no values.
+    pub fn uniform<I: IntoIterator<Item = i64>>(values: I) -> Distribution {
+        Distribution::from_weights(values.into_iter().map(|v| (v, 1)))
+    }
+
+    /// Each value with a chance in proportion to its weight.
+    ///
+    /// # Panics
+    ///
+    /// If the weights add up to 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rcmd::Distribution;
+    /// // A d6 loaded to roll a 6 half the time
+    /// let loaded = Distribution::from_weights(vec![(1, 1), (2, 1), (3, 1), (4, 1), (5, 1), (6, 5)]);
+    /// assert!(loaded.chance(6).to_string() == "1/2");
+    /// ```
+    pub fn from_weights<I: IntoIterator<Item = (i64, u64)>>(weights: I) -> Distribution {
+        Distribution::weighted(weights.into_iter().map(|(v, w)| (v, BigUint::from(w))))
+    }
+
+    /// Builds a distribution from weights in any order, combining the
+    /// weights of equal values.
+    pub(crate) fn weighted<I: IntoIterator<Item = (i64, BigUint)>>(weights: I) -> Distribution {
+        let mut merged: BTreeMap<i64, BigUint> = BTreeMap::new();
+        for (v, w) in weights {
+            *merged.entry(v).or_insert_with(BigUint::zero) += w;
+        }
+        let weights: Vec<(i64, BigUint)> = merged.into_iter().filter(|(_, w)| !w.is_zero()).collect();
+        assert!(!weights.is_empty(), "a distribution needs a value with some weight");
+
+        // Keep the weights as small as they can be, so that equal
+        // distributions compare equal
+        let common = weights.iter().fold(BigUint::zero(), |common, (_, w)| common.gcd(w));
+        let weights: Vec<(i64, BigUint)> = weights.into_iter().map(|(v, w)| (v, w / &common)).collect();
+        let total = weights.iter().map(|(_, w)| w).sum();
+        Distribution { weights, total }
+    }
+
+    /// Every possible value in increasing order with its chance.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (i64, BigRational)> + 'a {
+        self.weights.iter().map(move |&(v, ref w)| (v, self.ratio(w)))
+    }
+
+    /// The chance of exactly this value.
+    pub fn chance(&self, value: i64) -> BigRational {
+        match self.weights.binary_search_by_key(&value, |&(v, _)| v) {
+            Ok(i) => self.ratio(&self.weights[i].1),
+            Err(_) => BigRational::zero(),
+        }
+    }
+
+    /// The chance of this value or more.
+    pub fn at_least(&self, value: i64) -> BigRational {
+        self.ratio(&self.weights.iter().filter(|&&(v, _)| v >= value).map(|(_, w)| w).sum())
+    }
+
+    /// The chance of this value or less.
+    pub fn at_most(&self, value: i64) -> BigRational {
+        self.ratio(&self.weights.iter().filter(|&&(v, _)| v <= value).map(|(_, w)| w).sum())
+    }
+
+    /// The lowest possible value.
+    pub fn min(&self) -> i64 {
+        self.weights[0].0
+    }
+
+    /// The highest possible value.
+    pub fn max(&self) -> i64 {
+        self.weights[self.weights.len() - 1].0
+    }
+
+    /// The most likely value, or the lowest of them if several are equally
+    /// likely.
+    pub fn mode(&self) -> i64 {
+        let mut mode = &self.weights[0];
+        for entry in &self.weights[1..] {
+            if entry.1 > mode.1 {
+                mode = entry;
+            }
+        }
+        mode.0
+    }
+
+    /// The lowest value that at least `percent` percent of rolls come to or
+    /// under, so the 50th percentile is the median.
+    pub fn percentile(&self, percent: u32) -> i64 {
+        let needed = &self.total * BigUint::from(percent);
+        let mut below = BigUint::zero();
+        for &(v, ref w) in &self.weights {
+            below += w;
+            if &below * BigUint::from(100u32) >= needed {
+                return v;
+            }
+        }
+        self.max()
+    }
+
+    /// The distribution of a value from this less an independent value from
+    /// `other`, for comparing two rolls. `None` if a difference could
+    /// overflow.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rcmd::{Distribution, Rollable, RollCmd};
+    /// let a = "2d6".parse::<RollCmd>().unwrap().distribution().unwrap();
+    /// let b = "1d12".parse::<RollCmd>().unwrap().distribution().unwrap();
+    /// let diff = a.difference(&b).unwrap();
+    /// assert!(diff.at_least(1).to_string() == "1/2");
+    /// assert!(diff.mean().to_string() == "1/2");
+    /// ```
+    pub fn difference(&self, other: &Distribution) -> Option<Distribution> {
+        // Differences are at their extremes when the values are
+        self.min().checked_sub(other.max())?;
+        self.max().checked_sub(other.min())?;
+        self.combine(other, |a, b| a.checked_sub(b))
+    }
+
+    /// The average value.
+    pub fn mean(&self) -> BigRational {
+        self.moment(1)
+    }
+
+    /// The average squared distance from the mean.
+    pub fn variance(&self) -> BigRational {
+        let mean = self.mean();
+        self.moment(2) - &mean * &mean
+    }
+
+    /// The square root of the variance, which is rarely a fraction.
+    pub fn std_dev(&self) -> f64 {
+        self.variance().to_f64().unwrap_or(f64::NAN).sqrt()
+    }
+
+    /// The average of each value raised to a power.
+    fn moment(&self, power: u32) -> BigRational {
+        let sum: BigInt = self.weights.iter().map(|&(v, ref w)| BigInt::from(v).pow(power) * BigInt::from(w.clone())).sum();
+        BigRational::new(sum, BigInt::from(self.total.clone()))
+    }
+
+    fn ratio(&self, weight: &BigUint) -> BigRational {
+        BigRational::new(BigInt::from(weight.clone()), BigInt::from(self.total.clone()))
+    }
+}
+
+impl Distribution {
+    /// Each value with its weight, in increasing order.
+    pub(crate) fn weights(&self) -> &[(i64, BigUint)] {
+        &self.weights
+    }
+
+    /// The sum of the weights.
+    pub(crate) fn total(&self) -> &BigUint {
+        &self.total
+    }
+
+    /// Every possible value in increasing order.
+    pub(crate) fn values<'a>(&'a self) -> impl Iterator<Item = i64> + 'a {
+        self.weights.iter().map(|&(v, _)| v)
+    }
+
+    /// The chances as floating point, for estimates.
+    pub(crate) fn to_f64(&self) -> Vec<(i64, f64)> {
+        self.iter().map(|(v, p)| (v, p.to_f64().unwrap_or(0.0))).collect()
+    }
+
+    /// The distribution of `f` applied to the value.
+    pub(crate) fn map<F: Fn(i64) -> i64>(&self, f: F) -> Distribution {
+        Distribution::weighted(self.weights.iter().map(|&(v, ref w)| (f(v), w.clone())))
+    }
+
+    /// The distribution of `f` applied to a value from each of two
+    /// independent distributions, leaving out the pairs `f` rejects.
+    ///
+    /// Returns `None` if `f` rejects every pair.
+    pub(crate) fn combine<F: Fn(i64, i64) -> Option<i64>>(&self, other: &Distribution, f: F) -> Option<Distribution> {
+        let mut weights = Vec::with_capacity(self.weights.len() * other.weights.len());
+        for &(a, ref x) in &self.weights {
+            for &(b, ref y) in &other.weights {
+                if let Some(v) = f(a, b) {
+                    weights.push((v, x * y));
+                }
+            }
+        }
+        if weights.is_empty() { None } else { Some(Distribution::weighted(weights)) }
+    }
+
+    /// The distribution of the sum of a value from each, or `None` if a sum
+    /// could overflow.
+    pub(crate) fn add(&self, other: &Distribution) -> Option<Distribution> {
+        let mut sums: BTreeMap<i64, BigUint> = BTreeMap::new();
+        for &(a, ref x) in &self.weights {
+            for &(b, ref y) in &other.weights {
+                *sums.entry(a.checked_add(b)?).or_insert_with(BigUint::zero) += x * y;
+            }
+        }
+        Some(Distribution::weighted(sums))
+    }
+
+    /// The distribution of the sum of n independent values, or `None` if a
+    /// sum could overflow.
+    pub(crate) fn sum(&self, mut n: u32) -> Option<Distribution> {
+        let mut sum = Distribution::constant(0);
+        let mut doubled = self.clone();
+        while n > 0 {
+            if n & 1 == 1 {
+                sum = sum.add(&doubled)?;
+            }
+            n >>= 1;
+            if n > 0 {
+                doubled = doubled.add(&doubled)?;
+            }
+        }
+        Some(sum)
+    }
+
+    /// The distribution of `score` summed over the dice ranked `ranks` (from
+    /// lowest, counting from 0) out of `n` independent dice.
+    ///
+    /// Goes through the values from lowest up, tracking how many dice have
+    /// been ranked so far and what the kept ones add up to. When j of the r
+    /// dice left show the next value, they take the next j ranks, which
+    /// happens with weight C(r, j) w^j.
+    pub(crate) fn kept<G: Fn(i64) -> i64>(&self, n: u32, ranks: Range<usize>, score: G) -> Distribution {
+        let n = n as usize;
+        let mut states: BTreeMap<(usize, i64), BigUint> = BTreeMap::new();
+        states.insert((0, 0), BigUint::one());
+
+        for &(v, ref w) in &self.weights {
+            let mut next: BTreeMap<(usize, i64), BigUint> = BTreeMap::new();
+            for ((used, sum), weight) in states {
+                let left = n - used;
+                let mut ways = weight;
+                for j in 0..=left {
+                    let kept = (used + j).min(ranks.end).saturating_sub(used.max(ranks.start));
+                    let key = (used + j, sum + kept as i64 * score(v));
+                    *next.entry(key).or_insert_with(BigUint::zero) += &ways;
+                    ways = ways * w * BigUint::from(left - j) / BigUint::from(j + 1);
+                }
+            }
+            states = next;
+        }
+        Distribution::weighted(states.into_iter().filter(|&((used, _), _)| used == n).map(|((_, sum), w)| (sum, w)))
+    }
+}
+
+#[cfg(test)]
+mod distribution_tests {
+    use super::*;
+
+    fn ratio(n: i64, d: i64) -> BigRational {
+        BigRational::new(BigInt::from(n), BigInt::from(d))
+    }
+
+    #[test]
+    fn weights_are_merged_and_reduced() {
+        let dist = Distribution::from_weights(vec![(2, 2), (1, 2), (2, 4), (3, 0)]);
+        assert_eq!(dist, Distribution::from_weights(vec![(1, 1), (2, 3)]));
+        assert_eq!(dist.values().collect::<Vec<_>>(), [1, 2]);
+        assert_eq!(dist.chance(2), ratio(3, 4));
+        assert_eq!(dist.chance(3), ratio(0, 1));
+    }
+
+    #[test]
+    fn summary_statistics_are_exact() {
+        let d6 = Distribution::uniform(1..=6);
+        assert_eq!(d6.mean(), ratio(7, 2));
+        assert_eq!(d6.variance(), ratio(35, 12));
+        assert_eq!((d6.min(), d6.max(), d6.mode()), (1, 6, 1));
+        assert_eq!(d6.at_least(5), ratio(1, 3));
+        assert_eq!(d6.at_most(0), ratio(0, 1));
+        assert!((d6.std_dev() - (35.0f64 / 12.0).sqrt()).abs() < 1e-12);
+        assert_eq!((d6.percentile(0), d6.percentile(50), d6.percentile(51), d6.percentile(100)), (1, 3, 4, 6));
+    }
+
+    #[test]
+    fn sums_convolve() {
+        let d6 = Distribution::uniform(1..=6);
+        let dist = d6.sum(3).unwrap();
+        assert_eq!((dist.min(), dist.max()), (3, 18));
+        assert_eq!(dist.chance(10), ratio(27, 216));
+        assert_eq!(dist.mean(), ratio(21, 2));
+        assert_eq!(d6.sum(0), Some(Distribution::constant(0)));
+        assert_eq!(d6.sum(2), d6.add(&d6));
+
+        // Sums and differences that could overflow aren't worked out
+        let huge = Distribution::uniform(vec![1, i64::MAX / 2 + 1]);
+        assert!(huge.sum(2).is_none());
+        assert!(huge.add(&Distribution::constant(-1)).is_some());
+        let tiny = Distribution::uniform(vec![i64::MIN / 2 - 1, 0]);
+        assert!(huge.difference(&tiny).is_none());
+        assert!(Distribution::constant(0).difference(&Distribution::constant(i64::MIN)).is_none());
+        assert_eq!(tiny.difference(&tiny).map(|d| d.max()), Some(-(i64::MIN / 2 - 1)));
+    }
+
+    #[test]
+    fn kept_dice_follow_their_ranks() {
+        let d2 = Distribution::uniform(1..=2);
+        // Highest of two coins is 2 unless both are 1
+        assert_eq!(d2.kept(2, 1..2, |v| v), Distribution::from_weights(vec![(1, 1), (2, 3)]));
+        assert_eq!(d2.kept(2, 0..1, |v| v), Distribution::from_weights(vec![(1, 3), (2, 1)]));
+        assert_eq!(Some(d2.kept(2, 0..2, |v| v)), d2.sum(2));
+        assert_eq!(d2.kept(0, 0..0, |v| v), Distribution::constant(0));
+    }
+
+    #[test]
+    fn combining_can_leave_out_pairs() {
+        let d2 = Distribution::uniform(0..=1);
+        let quotient = Distribution::constant(4).combine(&d2, |a, b| a.checked_div(b)).unwrap();
+        assert_eq!(quotient, Distribution::constant(4));
+        assert_eq!(d2.combine(&Distribution::constant(0), |a, b| a.checked_div(b)), None);
+    }
+}
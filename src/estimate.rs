@@ -0,0 +1,466 @@
+//! Estimating distributions by rolling, for when working them out exactly
+//! would take too long.
+
+use std::thread;
+
+use num_traits::ToPrimitive;
+
+use distribution::Distribution;
+use expr::{EvalError, Expr};
+use rng::SeededRng;
+use Rollable;
+
+/// How many standard errors either side of an estimate a 95% confidence
+/// interval reaches.
+const Z: f64 = 1.96;
+
+/// The chance of every total, estimated from many rolls.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Estimate {
+    /// Every total rolled in increasing order, with how often it came up
+    counts: Vec<(i64, u64)>,
+    /// How many rolls were counted
+    trials: u64,
+    /// How many rolls were left out for dividing by zero
+    divided_by_zero: u64,
+}
+
+impl Estimate {
+    /// Counts up the totals of some rolls.
+    ///
+    /// # Panics
+    ///
+    /// If there are no totals.
+    pub fn from_totals<I: IntoIterator<Item = i64>>(totals: I) -> Estimate {
+        let mut totals: Vec<i64> = totals.into_iter().collect();
+        assert!(!totals.is_empty(), "an estimate needs at least one roll");
+        totals.sort();
+
+        let mut counts: Vec<(i64, u64)> = Vec::new();
+        for &v in &totals {
+            match counts.last_mut() {
+                Some(last) if last.0 == v => last.1 += 1,
+                _ => counts.push((v, 1)),
+            }
+        }
+        Estimate { counts, trials: totals.len() as u64, divided_by_zero: 0 }
+    }
+
+    /// How many rolls the estimate is based on.
+    pub fn trials(&self) -> u64 {
+        self.trials
+    }
+
+    /// How often a roll divided by zero. Those rolls aren't counted in the
+    /// other chances.
+    pub fn division_by_zero(&self) -> f64 {
+        self.divided_by_zero as f64 / (self.trials + self.divided_by_zero) as f64
+    }
+
+    /// Every total rolled in increasing order with how often it came up.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = (i64, f64)> + 'a {
+        self.counts.iter().map(move |&(v, n)| (v, n as f64 / self.trials as f64))
+    }
+
+    /// How often exactly this value came up.
+    pub fn chance(&self, value: i64) -> f64 {
+        self.share(|v| v == value)
+    }
+
+    /// How often this value or more came up.
+    pub fn at_least(&self, value: i64) -> f64 {
+        self.share(|v| v >= value)
+    }
+
+    /// How often this value or less came up.
+    pub fn at_most(&self, value: i64) -> f64 {
+        self.share(|v| v <= value)
+    }
+
+    /// The lowest total rolled.
+    pub fn min(&self) -> i64 {
+        self.counts[0].0
+    }
+
+    /// The highest total rolled.
+    pub fn max(&self) -> i64 {
+        self.counts[self.counts.len() - 1].0
+    }
+
+    /// The total rolled most often, or the lowest of them if several tied.
+    pub fn mode(&self) -> i64 {
+        let mut mode = self.counts[0];
+        for &entry in &self.counts[1..] {
+            if entry.1 > mode.1 {
+                mode = entry;
+            }
+        }
+        mode.0
+    }
+
+    /// The lowest total that at least `percent` percent of rolls came to or
+    /// under.
+    pub fn percentile(&self, percent: u32) -> i64 {
+        let mut below = 0;
+        for &(v, n) in &self.counts {
+            below += n;
+            if u128::from(below) * 100 >= u128::from(self.trials) * u128::from(percent) {
+                return v;
+            }
+        }
+        self.max()
+    }
+
+    /// The average total.
+    pub fn mean(&self) -> f64 {
+        self.counts.iter().map(|&(v, n)| v as f64 * n as f64).sum::<f64>() / self.trials as f64
+    }
+
+    /// The standard deviation of the totals.
+    pub fn std_dev(&self) -> f64 {
+        let mean = self.mean();
+        let squares: f64 = self.counts.iter().map(|&(v, n)| (v as f64 - mean).powi(2) * n as f64).sum();
+        (squares / (self.trials.max(2) - 1) as f64).sqrt()
+    }
+
+    /// The range the true average falls in with 95% confidence.
+    pub fn mean_interval(&self) -> (f64, f64) {
+        let margin = Z * self.std_dev() / (self.trials as f64).sqrt();
+        (self.mean() - margin, self.mean() + margin)
+    }
+
+    /// The range a true chance falls in with 95% confidence, given how often
+    /// it came up in these rolls. Uses the Wilson score interval, which
+    /// stays sensible for chances near 0 or 1.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rcmd::{Estimator, Expr};
+    /// let expr: Expr = "1d6".parse().unwrap();
+    /// let estimate = Estimator::new(7).trials(10000).estimate(&expr).unwrap();
+    /// let (lo, hi) = estimate.interval(estimate.chance(6));
+    /// assert!(lo < 1.0 / 6.0 && 1.0 / 6.0 < hi);
+    /// ```
+    pub fn interval(&self, chance: f64) -> (f64, f64) {
+        let n = self.trials as f64;
+        let z2 = Z * Z;
+        let centre = (chance + z2 / (2.0 * n)) / (1.0 + z2 / n);
+        let margin = Z / (1.0 + z2 / n) * (chance * (1.0 - chance) / n + z2 / (4.0 * n * n)).sqrt();
+        ((centre - margin).max(0.0), (centre + margin).min(1.0))
+    }
+
+    /// Every total rolled in increasing order with how many times it came
+    /// up.
+    pub(crate) fn counts(&self) -> &[(i64, u64)] {
+        &self.counts
+    }
+
+    fn share<F: Fn(i64) -> bool>(&self, f: F) -> f64 {
+        let n: u64 = self.counts.iter().filter(|&&(v, _)| f(v)).map(|&(_, n)| n).sum();
+        n as f64 / self.trials as f64
+    }
+}
+
+/// Works out the chances of an expression's totals, exactly when that's
+/// cheap enough and otherwise by rolling it many times over several threads.
+///
+/// Rolls come from `SeededRng`s seeded in turn from the given seed, so the
+/// same seed, trials and threads always give the same estimate.
+///
+/// # Examples
+///
+/// ```
+/// use rcmd::{Estimator, Expr, Odds};
+/// let estimator = Estimator::new(1234).trials(1000);
+///
+/// let expr: Expr = "4d6kh3".parse().unwrap();
+/// assert!(estimator.odds(&expr).unwrap().is_exact());
+///
+/// let expr: Expr = "1000d1000kh10".parse().unwrap();
+/// match estimator.odds(&expr).unwrap() {
+///     Odds::Estimated(estimate) => assert!(estimate.trials() == 1000),
+///     Odds::Exact(_) => unreachable!(),
+/// }
+/// ```
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Estimator {
+    seed: u64,
+    trials: u64,
+    threads: usize,
+    cost_limit: u64,
+}
+
+impl Estimator {
+    /// The default number of rolls an estimate is based on.
+    pub const DEFAULT_TRIALS: u64 = 100_000;
+
+    /// The most rolls an estimate may be based on, since every total is
+    /// kept until the estimate is made.
+    pub const MAX_TRIALS: u64 = 10_000_000;
+
+    /// The default limit on the arithmetic an exact distribution may take,
+    /// roughly a second's work.
+    pub const DEFAULT_COST_LIMIT: u64 = 10_000_000;
+
+    /// Roughly the most dice an estimate rolls altogether, a second or two
+    /// of work on one core. An estimate of a roll with so many dice that
+    /// the trials asked for would go over is based on fewer rolls, though
+    /// never fewer than `MIN_TRIALS`.
+    pub const MAX_DICE: u64 = 20_000_000;
+
+    /// The fewest rolls `MAX_DICE` cuts an estimate down to.
+    pub const MIN_TRIALS: u64 = 1_000;
+
+    // Construct a new Estimator rolling from seed, with as many threads as
+    // the machine has cores.
+    pub fn new(seed: u64) -> Estimator {
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        Estimator { seed, trials: Estimator::DEFAULT_TRIALS, threads, cost_limit: Estimator::DEFAULT_COST_LIMIT }
+    }
+
+    /// Base estimates on this many rolls, at least one and at most
+    /// `MAX_TRIALS`.
+    pub fn trials(mut self, trials: u64) -> Estimator {
+        self.trials = trials.clamp(1, Estimator::MAX_TRIALS);
+        self
+    }
+
+    /// Share the rolls between this many threads, at least one.
+    pub fn threads(mut self, threads: usize) -> Estimator {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Estimate rather than work out distributions that would take more
+    /// than this much arithmetic. 0 always estimates.
+    pub fn cost_limit(mut self, cost_limit: u64) -> Estimator {
+        self.cost_limit = cost_limit;
+        self
+    }
+
+    /// The exact distribution if it's known and within the cost limit, and
+    /// an estimate otherwise.
+    pub fn odds(&self, expr: &Expr) -> Result<Odds, EvalError> {
+        if expr.exact_cost() <= self.cost_limit as f64 {
+            if let Some(dist) = expr.distribution() {
+                return Ok(Odds::Exact(dist));
+            }
+        }
+        self.estimate(expr).map(Odds::Estimated)
+    }
+
+    /// Estimates the distribution by rolling the expression.
+    ///
+    /// Like an exact distribution, leaves out rolls that divide by zero;
+    /// fails only if every roll does, or the expression can't be rolled at
+    /// all. Rolls fewer times than asked if that many would take more than
+    /// `MAX_DICE` dice.
+    pub fn estimate(&self, expr: &Expr) -> Result<Estimate, EvalError> {
+        let trials = self.trials_for(expr);
+        let threads = (self.threads as u64).min(trials);
+        let mut seeds = SeededRng::new(self.seed);
+        let shares: Vec<(u64, u64)> = (0..threads)
+            .map(|i| (seeds.next_u64(), trials / threads + u64::from(i < trials % threads)))
+            .collect();
+
+        let results: Vec<Result<Vec<i64>, EvalError>> = thread::scope(|scope| {
+            let handles: Vec<_> = shares
+                .into_iter()
+                .map(|(seed, trials)| scope.spawn(move || roll_totals(expr, seed, trials)))
+                .collect();
+            handles.into_iter().map(|h| h.join().expect("an estimating thread panicked")).collect()
+        });
+
+        let mut totals = Vec::with_capacity(trials as usize);
+        for result in results {
+            totals.extend(result?);
+        }
+        if totals.is_empty() {
+            return Err(EvalError::DivideByZero);
+        }
+        let divided_by_zero = trials - totals.len() as u64;
+        Ok(Estimate { divided_by_zero, ..Estimate::from_totals(totals) })
+    }
+
+    /// How many times to roll the expression, keeping to `MAX_DICE` dice.
+    fn trials_for(&self, expr: &Expr) -> u64 {
+        let affordable = (Estimator::MAX_DICE as f64 / expr.dice_rolled()).max(Estimator::MIN_TRIALS as f64);
+        self.trials.min(affordable as u64)
+    }
+}
+
+/// Rolls the expression `trials` times, leaving out rolls that divide by
+/// zero.
+fn roll_totals(expr: &Expr, seed: u64, trials: u64) -> Result<Vec<i64>, EvalError> {
+    let mut rng = SeededRng::new(seed);
+    let mut totals = Vec::with_capacity(trials as usize);
+    for _ in 0..trials {
+        match expr.result(&mut rng) {
+            Ok(result) => totals.push(result.total()),
+            Err(EvalError::DivideByZero) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(totals)
+}
+
+/// The chances of an expression's totals, either worked out exactly or
+/// estimated.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Odds {
+    Exact(Distribution),
+    Estimated(Estimate),
+}
+
+impl Odds {
+    /// Whether the chances are exact.
+    pub fn is_exact(&self) -> bool {
+        matches!(*self, Odds::Exact(_))
+    }
+
+    /// Every total in increasing order with its chance.
+    pub fn chances(&self) -> Vec<(i64, f64)> {
+        match *self {
+            Odds::Exact(ref dist) => dist.to_f64(),
+            Odds::Estimated(ref estimate) => estimate.iter().collect(),
+        }
+    }
+
+    /// The chance of exactly this value.
+    pub fn chance(&self, value: i64) -> f64 {
+        match *self {
+            Odds::Exact(ref dist) => dist.chance(value).to_f64().unwrap_or(0.0),
+            Odds::Estimated(ref estimate) => estimate.chance(value),
+        }
+    }
+
+    /// The lowest total.
+    pub fn min(&self) -> i64 {
+        match *self {
+            Odds::Exact(ref dist) => dist.min(),
+            Odds::Estimated(ref estimate) => estimate.min(),
+        }
+    }
+
+    /// The highest total.
+    pub fn max(&self) -> i64 {
+        match *self {
+            Odds::Exact(ref dist) => dist.max(),
+            Odds::Estimated(ref estimate) => estimate.max(),
+        }
+    }
+
+    /// The most likely total.
+    pub fn mode(&self) -> i64 {
+        match *self {
+            Odds::Exact(ref dist) => dist.mode(),
+            Odds::Estimated(ref estimate) => estimate.mode(),
+        }
+    }
+
+    /// The lowest total that at least `percent` percent of rolls come to or
+    /// under.
+    pub fn percentile(&self, percent: u32) -> i64 {
+        match *self {
+            Odds::Exact(ref dist) => dist.percentile(percent),
+            Odds::Estimated(ref estimate) => estimate.percentile(percent),
+        }
+    }
+
+    /// The average total.
+    pub fn mean(&self) -> f64 {
+        match *self {
+            Odds::Exact(ref dist) => dist.mean().to_f64().unwrap_or(f64::NAN),
+            Odds::Estimated(ref estimate) => estimate.mean(),
+        }
+    }
+
+    /// The standard deviation of the totals.
+    pub fn std_dev(&self) -> f64 {
+        match *self {
+            Odds::Exact(ref dist) => dist.std_dev(),
+            Odds::Estimated(ref estimate) => estimate.std_dev(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod estimate_tests {
+    use super::*;
+    use {Explode, RollCmd};
+
+    #[test]
+    fn counts_up_totals() {
+        let estimate = Estimate::from_totals(vec![3, 1, 3, 2]);
+        assert_eq!(estimate.iter().collect::<Vec<_>>(), [(1, 0.25), (2, 0.25), (3, 0.5)]);
+        assert_eq!((estimate.trials(), estimate.min(), estimate.max(), estimate.mode()), (4, 1, 3, 3));
+        assert_eq!((estimate.at_least(2), estimate.at_most(2)), (0.75, 0.5));
+        assert_eq!((estimate.mean(), estimate.percentile(50), estimate.percentile(51)), (2.25, 2, 3));
+    }
+
+    #[test]
+    fn intervals_narrow_with_more_rolls() {
+        let few = Estimate::from_totals(vec![0, 1]);
+        let many = Estimate::from_totals((0..10000).map(|i| i % 2));
+        let width = |(lo, hi): (f64, f64)| hi - lo;
+        assert!(width(many.interval(0.5)) < width(few.interval(0.5)));
+        assert!(width(many.mean_interval()) < 0.02);
+        assert_eq!(many.interval(0.0).0, 0.0);
+    }
+
+    #[test]
+    fn estimates_are_reproducible_and_close() {
+        let expr: Expr = "3d6".parse().unwrap();
+        let estimator = Estimator::new(42).trials(20000).threads(3);
+        let estimate = estimator.estimate(&expr).unwrap();
+        assert_eq!(estimate, estimator.estimate(&expr).unwrap());
+        assert_eq!(estimate.trials(), 20000);
+        let (lo, hi) = estimate.mean_interval();
+        assert!(lo < 10.5 && 10.5 < hi, "{:?}", (lo, hi));
+        assert!((3..=18).contains(&estimate.min()) && (3..=18).contains(&estimate.max()));
+    }
+
+    #[test]
+    fn estimates_leave_out_division_by_zero() {
+        let estimator = Estimator::new(1).trials(1000);
+        let estimate = estimator.estimate(&"12/(1d3-2)".parse().unwrap()).unwrap();
+        assert!(estimate.iter().all(|(v, _)| v == 12 || v == -12));
+        assert!(estimate.trials() < 1000);
+        assert_eq!(estimate.division_by_zero(), (1000 - estimate.trials()) as f64 / 1000.0);
+        assert!((estimate.division_by_zero() - 1.0 / 3.0).abs() < 0.1);
+        assert_eq!(estimator.estimate(&"1d6/0".parse().unwrap()), Err(EvalError::DivideByZero));
+        assert_eq!(estimator.estimate(&"d{a,b}+1".parse().unwrap()), Err(EvalError::SymbolicArithmetic));
+    }
+
+    #[test]
+    fn costly_distributions_are_estimated() {
+        let estimator = Estimator::new(1).trials(100);
+        assert!(estimator.odds(&"4d6kh3".parse().unwrap()).unwrap().is_exact());
+        assert!(!estimator.cost_limit(0).odds(&"4d6kh3".parse().unwrap()).unwrap().is_exact());
+        assert!(!estimator.odds(&"1000d1000kh10".parse().unwrap()).unwrap().is_exact());
+        assert!(!estimator.odds(&"d{-5,9223372036854775807}".parse().unwrap()).unwrap().is_exact());
+        assert!(!estimator.odds(&"d{-5,9223372036854775807}-d6".parse().unwrap()).unwrap().is_exact());
+
+        // A single die needs no adding up, however many sides it has
+        assert!(estimator.odds(&"d1000000".parse().unwrap()).unwrap().is_exact());
+        assert!(estimator.odds(&"3d100".parse().unwrap()).unwrap().is_exact());
+        assert!(!estimator.odds(&"3d1000000".parse().unwrap()).unwrap().is_exact());
+        assert!(!estimator.odds(&"10000d6".parse().unwrap()).unwrap().is_exact());
+    }
+
+    #[test]
+    fn trials_are_limited() {
+        assert_eq!(Estimator::new(1).trials(u64::MAX).trials, Estimator::MAX_TRIALS);
+        assert_eq!(Estimator::new(1).trials(0).trials, 1);
+
+        // Rolls with many dice are estimated from fewer rolls
+        let estimator = Estimator::new(1).trials(Estimator::MAX_TRIALS);
+        let trials = |s: &str| estimator.trials_for(&s.parse().unwrap());
+        assert_eq!(trials("10000d6"), Estimator::MAX_DICE / 10000);
+        assert_eq!(trials("1000d6+1000d6*1000d6"), Estimator::MAX_DICE / 3000);
+        assert_eq!(trials("100d1!"), Estimator::MAX_DICE / (100 * (Explode::DEFAULT_LIMIT as u64 + 1)));
+        assert_eq!(estimator.trials_for(&Expr::Roll(RollCmd::new(200_000, 2))), Estimator::MIN_TRIALS);
+        assert_eq!(Estimator::new(1).trials_for(&"2d6".parse().unwrap()), Estimator::DEFAULT_TRIALS);
+    }
+}
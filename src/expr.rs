@@ -0,0 +1,857 @@
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use num_rational::BigRational;
+use num_traits::{One, ToPrimitive, Zero};
+
+use distribution::Distribution;
+use json::Json;
+use parse::{self, ParseError};
+use rng::DiceRng;
+use {RollCmd, RollResult, Rollable};
+
+/// An arithmetic operator joining two sub-expressions.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+impl Op {
+    fn symbol(self) -> &'static str {
+        match self {
+            Op::Add => "+",
+            Op::Sub => "-",
+            Op::Mul => "*",
+            Op::Div => "/",
+        }
+    }
+
+    /// Applies the operator, or returns `None` if the result doesn't fit in
+    /// an `i64` or divides by zero.
+    fn apply(self, a: i64, b: i64) -> Option<i64> {
+        match self {
+            Op::Add => a.checked_add(b),
+            Op::Sub => a.checked_sub(b),
+            Op::Mul => a.checked_mul(b),
+            Op::Div => a.checked_div(b),
+        }
+    }
+
+    fn precedence(self) -> u8 {
+        match self {
+            Op::Add | Op::Sub => 1,
+            Op::Mul | Op::Div => 2,
+        }
+    }
+}
+
+/// A parsed dice expression such as `2d6+1d4+3` or `(1d8+2)*2`.
+///
+/// Expressions are built from dice terms, integer literals, the four basic
+/// arithmetic operators, parentheses and unary minus. Division rounds toward
+/// zero.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Expr {
+    Roll(RollCmd),
+    Num(u32),
+    Neg(Box<Expr>),
+    BinOp(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    /// Evaluates the expression, rolling every dice term it contains.
+    ///
+    /// Dice are rolled in order from left to right.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rcmd::{Expr, MaxRng};
+    /// let expr: Expr = "2d6+3".parse().unwrap();
+    /// let result = expr.result(&mut MaxRng).unwrap();
+    /// assert!(result.total() == 15);
+    /// ```
+    pub fn result<R: DiceRng + ?Sized>(&self, rng: &mut R) -> Result<ExprResult, EvalError> {
+        self.eval(rng)
+    }
+
+    /// The exact chance that a roll divides by zero. Always known when no
+    /// divisor can be zero, and otherwise whenever the distribution is. The
+    /// distribution leaves those rolls out, so its chances are of the rolls
+    /// that don't.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rcmd::Expr;
+    /// let expr: Expr = "1d6/(1d2-1)".parse().unwrap();
+    /// assert!(expr.division_by_zero().unwrap().to_string() == "1/2");
+    /// ```
+    pub fn division_by_zero(&self) -> Option<BigRational> {
+        if !self.may_divide_by_zero() {
+            return Some(BigRational::zero());
+        }
+        self.outcomes().map(|(_, zero)| zero)
+    }
+
+    /// Rolls every d100 in the expression as a tens die and a units die.
+    /// See `RollCmd::percentile`.
+    pub fn percentile(self) -> Expr {
+        match self {
+            Expr::Roll(cmd) => Expr::Roll(cmd.percentile()),
+            Expr::Neg(inner) => Expr::Neg(Box::new(inner.percentile())),
+            Expr::BinOp(op, lhs, rhs) => Expr::BinOp(op, Box::new(lhs.percentile()), Box::new(rhs.percentile())),
+            expr => expr,
+        }
+    }
+
+    fn eval<R: DiceRng + ?Sized>(&self, rng: &mut R) -> Result<ExprResult, EvalError> {
+        match *self {
+            Expr::Roll(ref cmd) => {
+                let roll = cmd.result(rng);
+                let total = roll.checked_total().ok_or(EvalError::Overflow)?;
+                Ok(ExprResult::new(Node::Roll(roll), total))
+            }
+            Expr::Num(n) => Ok(ExprResult::new(Node::Num(n), i64::from(n))),
+            Expr::Neg(ref inner) => {
+                let inner = inner.eval(rng)?;
+                if inner.is_symbolic() {
+                    return Err(EvalError::SymbolicArithmetic);
+                }
+                let total = inner.total.checked_neg().ok_or(EvalError::Overflow)?;
+                Ok(ExprResult::new(Node::Neg(Box::new(inner)), total))
+            }
+            Expr::BinOp(op, ref lhs, ref rhs) => {
+                let (lhs, rhs) = (lhs.eval(rng)?, rhs.eval(rng)?);
+                if lhs.is_symbolic() || rhs.is_symbolic() {
+                    return Err(EvalError::SymbolicArithmetic);
+                }
+                if op == Op::Div && rhs.total == 0 {
+                    return Err(EvalError::DivideByZero);
+                }
+                let total = op.apply(lhs.total, rhs.total).ok_or(EvalError::Overflow)?;
+                Ok(ExprResult::new(Node::BinOp(op, Box::new(lhs), Box::new(rhs)), total))
+            }
+        }
+    }
+}
+
+impl Rollable for Expr {
+    type Output = Result<ExprResult, EvalError>;
+
+    fn roll(&self, rng: &mut dyn DiceRng) -> Result<ExprResult, EvalError> {
+        self.result(rng)
+    }
+
+    /// Leaves out any outcome that would divide by zero.
+    fn min(&self) -> i64 {
+        self.bounds().0
+    }
+
+    /// Leaves out any outcome that would divide by zero.
+    fn max(&self) -> i64 {
+        self.bounds().1
+    }
+
+    /// Dice terms are independent, so sums, differences and products are
+    /// exact. A quotient is worked out from the distribution when it's known
+    /// and otherwise estimated as the ratio of the expected values.
+    fn expected(&self) -> f64 {
+        match *self {
+            Expr::Roll(ref cmd) => cmd.expected(),
+            Expr::Num(n) => f64::from(n),
+            Expr::Neg(ref inner) => -inner.expected(),
+            Expr::BinOp(op, ref lhs, ref rhs) => {
+                let (lhs, rhs) = (lhs.expected(), rhs.expected());
+                match op {
+                    Op::Add => lhs + rhs,
+                    Op::Sub => lhs - rhs,
+                    Op::Mul => lhs * rhs,
+                    Op::Div => match self.distribution() {
+                        Some(dist) => dist.mean().to_f64().unwrap_or(f64::NAN),
+                        None => lhs / rhs,
+                    },
+                }
+            }
+        }
+    }
+
+    fn notation(&self) -> String {
+        self.to_string()
+    }
+
+    /// Known when every dice term's distribution is, none of them with
+    /// named faces takes part in arithmetic, and no total can overflow. Like
+    /// the bounds, leaves out any outcome that would divide by zero; see
+    /// `division_by_zero` for how likely those are.
+    fn distribution(&self) -> Option<Distribution> {
+        self.outcomes().map(|(dist, _)| dist)
+    }
+}
+
+impl Expr {
+    /// The distribution of the totals of rolls that don't divide by zero,
+    /// with the chance that a roll does. Sub-expressions are rolled
+    /// independently, so a roll avoids dividing by zero when both sides of
+    /// every operation do and the divisor isn't zero.
+    fn outcomes(&self) -> Option<(Distribution, BigRational)> {
+        match *self {
+            Expr::Roll(ref cmd) => cmd.distribution().map(|dist| (dist, BigRational::zero())),
+            Expr::Num(n) => Some((Distribution::constant(i64::from(n)), BigRational::zero())),
+            Expr::Neg(ref inner) if !inner.is_symbolic() => {
+                let (dist, zero) = inner.outcomes()?;
+                dist.min().checked_neg()?;
+                Some((dist.map(|v| -v), zero))
+            }
+            Expr::BinOp(op, ref lhs, ref rhs) if !lhs.is_symbolic() && !rhs.is_symbolic() => {
+                let ((lhs, lhs_zero), (rhs, rhs_zero)) = (lhs.outcomes()?, rhs.outcomes()?);
+                let mut fine = (BigRational::one() - lhs_zero) * (BigRational::one() - rhs_zero);
+                if op == Op::Div {
+                    fine *= BigRational::one() - rhs.chance(0);
+                    // The only other quotient that overflows
+                    if !rhs.chance(-1).is_zero() {
+                        op.apply(lhs.min(), -1)?;
+                    }
+                } else {
+                    // Sums, differences and products are at their extremes
+                    // when both operands are, so checking those is enough
+                    for &a in &[lhs.min(), lhs.max()] {
+                        for &b in &[rhs.min(), rhs.max()] {
+                            op.apply(a, b)?;
+                        }
+                    }
+                }
+                let dist = lhs.combine(&rhs, |a, b| op.apply(a, b))?;
+                Some((dist, BigRational::one() - fine))
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether any divisor's range takes in zero.
+    fn may_divide_by_zero(&self) -> bool {
+        match *self {
+            Expr::Roll(_) | Expr::Num(_) => false,
+            Expr::Neg(ref inner) => inner.may_divide_by_zero(),
+            Expr::BinOp(op, ref lhs, ref rhs) => {
+                let (lo, hi) = rhs.bounds();
+                (op == Op::Div && lo <= 0 && 0 <= hi) || lhs.may_divide_by_zero() || rhs.may_divide_by_zero()
+            }
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match *self {
+            Expr::BinOp(op, _, _) => op.precedence(),
+            _ => 3,
+        }
+    }
+
+    /// A rough count of the arithmetic working out the exact distribution
+    /// takes. Combining two sub-expressions pairs up every value of one with
+    /// every value of the other.
+    pub(crate) fn exact_cost(&self) -> f64 {
+        let values = |expr: &Expr| {
+            let (lo, hi) = expr.bounds();
+            (i128::from(hi) - i128::from(lo) + 1) as f64
+        };
+        match *self {
+            Expr::Roll(ref cmd) => cmd.exact_cost(),
+            Expr::Num(_) => 0.0,
+            Expr::Neg(ref inner) => inner.exact_cost(),
+            Expr::BinOp(_, ref lhs, ref rhs) => lhs.exact_cost() + rhs.exact_cost() + values(lhs) * values(rhs),
+        }
+    }
+
+    /// How many dice rolling the expression takes on average.
+    pub(crate) fn dice_rolled(&self) -> f64 {
+        match *self {
+            Expr::Roll(ref cmd) => cmd.dice_rolled(),
+            Expr::Num(_) => 0.0,
+            Expr::Neg(ref inner) => inner.dice_rolled(),
+            Expr::BinOp(_, ref lhs, ref rhs) => lhs.dice_rolled() + rhs.dice_rolled(),
+        }
+    }
+
+    /// Whether this is a roll of dice with named faces.
+    fn is_symbolic(&self) -> bool {
+        match *self {
+            Expr::Roll(ref cmd) => cmd.die.is_symbolic(),
+            _ => false,
+        }
+    }
+
+    fn bounds(&self) -> (i64, i64) {
+        let extremes = |values: &[i64]| {
+            let lo = values.iter().cloned().min().unwrap_or(0);
+            let hi = values.iter().cloned().max().unwrap_or(0);
+            (lo, hi)
+        };
+        match *self {
+            Expr::Roll(ref cmd) => (cmd.min(), cmd.max()),
+            Expr::Num(n) => (i64::from(n), i64::from(n)),
+            Expr::Neg(ref inner) => {
+                let (lo, hi) = inner.bounds();
+                (hi.saturating_neg(), lo.saturating_neg())
+            }
+            // Saturates rather than overflowing, since a total that doesn't
+            // fit fails to roll anyway
+            Expr::BinOp(op, ref lhs, ref rhs) => {
+                let ((a, b), (c, d)) = (lhs.bounds(), rhs.bounds());
+                match op {
+                    Op::Add => (a.saturating_add(c), b.saturating_add(d)),
+                    Op::Sub => (a.saturating_sub(d), b.saturating_sub(c)),
+                    Op::Mul => extremes(&[a.saturating_mul(c), a.saturating_mul(d), b.saturating_mul(c), b.saturating_mul(d)]),
+                    Op::Div => {
+                        // Truncating division is monotonic on either side of
+                        // zero, so the extremes are at the ends of the range
+                        // or just beside zero
+                        let divisors = [c, d, -1, 1];
+                        let quotients: Vec<_> = divisors
+                            .iter()
+                            .filter(|&&x| x != 0 && c <= x && x <= d)
+                            .flat_map(|&x| vec![a.saturating_div(x), b.saturating_div(x)])
+                            .collect();
+                        extremes(&quotients)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Writes the expression with parentheses only where precedence
+    /// requires them.
+    fn render(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expr::Roll(ref cmd) => write!(f, "{}", cmd),
+            Expr::Num(n) => write!(f, "{}", n),
+            Expr::Neg(ref inner) => {
+                write!(f, "-")?;
+                inner.render_wrapped(f, inner.precedence() < 3)
+            }
+            Expr::BinOp(op, ref lhs, ref rhs) => {
+                lhs.render_wrapped(f, lhs.precedence() < op.precedence())?;
+                write!(f, "{}", op.symbol())?;
+                let tighter = match op {
+                    Op::Sub | Op::Div => rhs.precedence() <= op.precedence(),
+                    Op::Add | Op::Mul => rhs.precedence() < op.precedence(),
+                };
+                rhs.render_wrapped(f, tighter)
+            }
+        }
+    }
+
+    fn render_wrapped(&self, f: &mut fmt::Formatter, parens: bool) -> fmt::Result {
+        if parens {
+            write!(f, "(")?;
+            self.render(f)?;
+            write!(f, ")")
+        } else {
+            self.render(f)
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    /// Writes the expression in canonical notation. A lone number is wrapped
+    /// in parentheses so it doesn't read back as a die.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::Expr;
+    /// let expr: Expr = "(d20 + 5) - (2 * 3)".parse().unwrap();
+    /// assert!(expr.to_string() == "1d20+5-2*3");
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expr::Num(n) => write!(f, "({})", n),
+            _ => self.render(f),
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Expr, <Expr as FromStr>::Err> {
+        parse::parse_expr(s)
+    }
+}
+
+/// An expression rolled several times over, such as `6x4d6kh3` for a set of
+/// ability scores, with an optional label and comment to tell its results
+/// apart, as in `sword: 1d8+3 # magic`.
+///
+/// # Examples
+///
+/// ```
+/// use rcmd::{MaxRng, Repeat};
+/// let repeat: Repeat = "6x4d6kh3".parse().unwrap();
+/// let results = repeat.results(&mut MaxRng).unwrap();
+/// assert!(results.len() == 6 && results.iter().all(|r| r.total() == 18));
+///
+/// let repeat: Repeat = "sword: 1d8+3 # magic".parse().unwrap();
+/// assert!(repeat.label() == Some("sword") && repeat.comment() == Some("magic"));
+/// ```
+#[derive(Debug, Eq, PartialEq)]
+pub struct Repeat {
+    times: u32,
+    expr: Expr,
+    label: Option<String>,
+    comment: Option<String>,
+}
+
+impl Repeat {
+    // Construct a Repeat rolling expr the given number of times.
+    pub fn new(times: u32, expr: Expr) -> Repeat {
+        Repeat { times, expr, label: None, comment: None }
+    }
+
+    /// Label every result, e.g. `sword` to display `sword: ...`.
+    pub fn labelled(mut self, label: &str) -> Repeat {
+        self.label = Some(label.to_string());
+        self
+    }
+
+    /// Comment on every result, e.g. `magic` for `... # magic`.
+    pub fn commented(mut self, comment: &str) -> Repeat {
+        self.comment = Some(comment.to_string());
+        self
+    }
+
+    /// The label given to every result, if any.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// The comment on every result, if any.
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /// How many times the expression is rolled.
+    pub fn times(&self) -> u32 {
+        self.times
+    }
+
+    /// The expression that's rolled.
+    pub fn expr(&self) -> &Expr {
+        &self.expr
+    }
+
+    /// Gives up the expression, forgetting how many times to roll it and any
+    /// label or comment.
+    pub fn into_expr(self) -> Expr {
+        self.expr
+    }
+
+    /// Rolls every d100 as a tens die and a units die. See
+    /// `RollCmd::percentile`.
+    pub fn percentile(self) -> Repeat {
+        Repeat { expr: self.expr.percentile(), ..self }
+    }
+
+    /// Rolls the expression once, labelled and commented.
+    pub fn result<R: DiceRng + ?Sized>(&self, rng: &mut R) -> Result<ExprResult, EvalError> {
+        let mut result = self.expr.result(rng)?;
+        result.label = self.label.clone();
+        result.comment = self.comment.clone();
+        Ok(result)
+    }
+
+    /// Rolls the expression the given number of times, each independently
+    /// of the others, failing if any roll does.
+    pub fn results<R: DiceRng + ?Sized>(&self, rng: &mut R) -> Result<Vec<ExprResult>, EvalError> {
+        (0..self.times).map(|_| self.result(rng)).collect()
+    }
+}
+
+impl fmt::Display for Repeat {
+    /// Writes the expression in canonical notation, after the number of
+    /// times it's rolled unless that's once, and between its label and
+    /// comment if it has them.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref label) = self.label {
+            write!(f, "{}: ", label)?;
+        }
+        if self.times != 1 {
+            write!(f, "{}x", self.times)?;
+        }
+        write!(f, "{}", self.expr)?;
+        if let Some(ref comment) = self.comment {
+            write!(f, " # {}", comment)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Repeat {
+    type Err = ParseError;
+
+    /// Parses an expression with an optional count of repetitions in front,
+    /// like `6x4d6kh3`; without one it's rolled once. A label and comment
+    /// may go around it, like `sword: 1d8+3 # magic`.
+    fn from_str(s: &str) -> Result<Repeat, <Repeat as FromStr>::Err> {
+        parse::parse_repeat(s)
+    }
+}
+
+/// An error raised while evaluating an `Expr`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum EvalError {
+    DivideByZero,
+    /// A total too large for an `i64`, e.g. `4294967295*4294967295`
+    Overflow,
+    /// Dice with named faces used with an operator, e.g. `d{hit,miss}+1`
+    SymbolicArithmetic,
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            EvalError::DivideByZero => write!(f, "Division by zero"),
+            EvalError::Overflow => write!(f, "Total too large (maximum is {})", i64::MAX),
+            EvalError::SymbolicArithmetic => write!(f, "Dice with named faces have no value to calculate with"),
+        }
+    }
+}
+
+impl Error for EvalError {}
+
+#[derive(Debug)]
+enum Node {
+    Roll(RollResult),
+    Num(u32),
+    Neg(Box<ExprResult>),
+    BinOp(Op, Box<ExprResult>, Box<ExprResult>),
+}
+
+/// The outcome of evaluating an `Expr`.
+///
+/// Keeps the individual roll of every dice term alongside the final total, so
+/// the whole calculation can be displayed, along with the label and comment
+/// of the `Repeat` it was rolled from.
+#[derive(Debug)]
+pub struct ExprResult {
+    node: Node,
+    total: i64,
+    label: Option<String>,
+    comment: Option<String>,
+}
+
+impl ExprResult {
+    fn new(node: Node, total: i64) -> ExprResult {
+        ExprResult { node, total, label: None, comment: None }
+    }
+
+    /// Returns the value of the whole expression.
+    pub fn total(&self) -> i64 {
+        self.total
+    }
+
+    /// The label of the `Repeat` this was rolled from, if any.
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// The comment on the `Repeat` this was rolled from, if any.
+    pub fn comment(&self) -> Option<&str> {
+        self.comment.as_deref()
+    }
+
+    /// Whether this is a roll of dice with named faces.
+    fn is_symbolic(&self) -> bool {
+        matches!(self.node, Node::Roll(ref roll) if roll.tally().is_some())
+    }
+
+    /// Returns the result of every dice term, left to right.
+    pub fn rolls(&self) -> Vec<&RollResult> {
+        let mut rolls = Vec::new();
+        self.collect_rolls(&mut rolls);
+        rolls
+    }
+
+    fn collect_rolls<'a>(&'a self, out: &mut Vec<&'a RollResult>) {
+        match self.node {
+            Node::Roll(ref roll) => out.push(roll),
+            Node::Num(_) => {}
+            Node::Neg(ref inner) => inner.collect_rolls(out),
+            Node::BinOp(_, ref lhs, ref rhs) => {
+                lhs.collect_rolls(out);
+                rhs.collect_rolls(out);
+            }
+        }
+    }
+
+    /// The calculation as JSON; see the `json` module for the layout.
+    pub(crate) fn to_json(&self) -> Json {
+        let mut json = self.node_json();
+        if let Json::Object(ref mut fields) = json {
+            if let Some(ref label) = self.label {
+                fields.push(("label".to_string(), label.as_str().into()));
+            }
+            if let Some(ref comment) = self.comment {
+                fields.push(("comment".to_string(), comment.as_str().into()));
+            }
+        }
+        json
+    }
+
+    fn node_json(&self) -> Json {
+        match self.node {
+            Node::Roll(ref roll) => roll.to_json(),
+            Node::Num(n) => Json::object(vec![("number", Json::Int(i64::from(n))), ("total", Json::Int(self.total))]),
+            Node::Neg(ref inner) => Json::object(vec![("negate", inner.to_json()), ("total", Json::Int(self.total))]),
+            Node::BinOp(op, ref lhs, ref rhs) => Json::object(vec![
+                ("op", op.symbol().into()),
+                ("lhs", lhs.to_json()),
+                ("rhs", rhs.to_json()),
+                ("total", Json::Int(self.total)),
+            ]),
+        }
+    }
+
+    fn precedence(&self) -> u8 {
+        match self.node {
+            Node::BinOp(op, _, _) => op.precedence(),
+            _ => 3,
+        }
+    }
+
+    /// Writes the calculation without the trailing total, parenthesizing
+    /// sub-expressions only where precedence requires it.
+    fn render(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.node {
+            Node::Roll(ref roll) => {
+                let as_strings: Vec<_> = roll.iter().map(|n| n.to_string()).collect();
+                write!(f, "[{}]", as_strings.join(", "))
+            }
+            Node::Num(n) => write!(f, "{}", n),
+            Node::Neg(ref inner) => {
+                write!(f, "-")?;
+                inner.render_wrapped(f, inner.precedence() < 3)
+            }
+            Node::BinOp(op, ref lhs, ref rhs) => {
+                lhs.render_wrapped(f, lhs.precedence() < op.precedence())?;
+                write!(f, " {} ", op.symbol())?;
+                let tighter = match op {
+                    Op::Sub | Op::Div => rhs.precedence() <= op.precedence(),
+                    Op::Add | Op::Mul => rhs.precedence() < op.precedence(),
+                };
+                rhs.render_wrapped(f, tighter)
+            }
+        }
+    }
+
+    fn render_wrapped(&self, f: &mut fmt::Formatter, parens: bool) -> fmt::Result {
+        if parens {
+            write!(f, "(")?;
+            self.render(f)?;
+            write!(f, ")")
+        } else {
+            self.render(f)
+        }
+    }
+}
+
+impl fmt::Display for ExprResult {
+    /// A lone dice term displays exactly like its `RollResult`; anything more
+    /// complex shows each roll in brackets followed by the total.
+    ///
+    /// A labelled result leads with its label and total and follows them
+    /// with the calculation, which is easier to pick out of a log of many
+    /// rolls. Dice pools and named faces keep their usual display after the
+    /// label, since their total is a count. A comment is left for the
+    /// caller to show.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::{Expr, MaxRng, Repeat};
+    /// let expr: Expr = "2d6+3".parse().unwrap();
+    /// let result = expr.result(&mut MaxRng).unwrap();
+    /// assert!(result.to_string() == "[6, 6] + 3 (Total: 15)");
+    ///
+    /// let repeat: Repeat = "Perception: 1d20+5".parse().unwrap();
+    /// let result = repeat.result(&mut MaxRng).unwrap();
+    /// assert!(result.to_string() == "Perception: 25 ([20] + 5)");
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let label = match self.label {
+            Some(ref label) => label,
+            None => {
+                return match self.node {
+                    Node::Roll(ref roll) => write!(f, "{}", roll),
+                    _ => {
+                        self.render(f)?;
+                        write!(f, " (Total: {})", self.total)
+                    }
+                };
+            }
+        };
+        match self.node {
+            Node::Roll(ref roll) if roll.successes().is_some() || roll.tally().is_some() => {
+                write!(f, "{}: {}", label, roll)
+            }
+            Node::Roll(ref roll) => {
+                let as_strings: Vec<_> = roll.iter().map(|d| d.to_string()).collect();
+                let dice = if as_strings.is_empty() { "No dice".to_string() } else { as_strings.join(", ") };
+                write!(f, "{}: {} ({})", label, self.total, dice)
+            }
+            _ => {
+                write!(f, "{}: {} (", label, self.total)?;
+                self.render(f)?;
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod expr_tests {
+    use super::*;
+    use rng::MaxRng;
+
+    fn roll_max(s: &str) -> ExprResult {
+        s.parse::<Expr>().unwrap().result(&mut MaxRng).unwrap()
+    }
+
+    #[test]
+    fn can_parse_compound_exprs() {
+        let expr: Expr = "2d6+1d4+3".parse().unwrap();
+        let expected = Expr::BinOp(
+            Op::Add,
+            Box::new(Expr::BinOp(
+                Op::Add,
+                Box::new(Expr::Roll(RollCmd::new(2, 6))),
+                Box::new(Expr::Roll(RollCmd::new(1, 4))),
+            )),
+            Box::new(Expr::Num(3)),
+        );
+        assert!(expr == expected);
+    }
+
+    #[test]
+    fn lone_numbers_are_dice() {
+        let expr: Expr = "20".parse().unwrap();
+        assert!(expr == Expr::Roll(RollCmd::new(1, 20)));
+    }
+
+    #[test]
+    fn respects_precedence_and_parens() {
+        assert!(roll_max("2+3*4").total() == 14);
+        assert!(roll_max("(1d8+2)*2").total() == 20);
+        assert!(roll_max("-d4-2").total() == -6);
+        assert!(roll_max("10-4-3").total() == 3);
+        assert!(roll_max("7/2").total() == 3);
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        let expr: Expr = "1d6/0".parse().unwrap();
+        assert!(expr.result(&mut MaxRng).unwrap_err() == EvalError::DivideByZero);
+    }
+
+    #[test]
+    fn overflow_is_an_error() {
+        for s in &["4294967295*4294967295", "-1-4294967295*2147483649", "2d{9223372036854775807}"] {
+            let expr: Expr = s.parse().unwrap();
+            assert!(expr.result(&mut MaxRng).unwrap_err() == EvalError::Overflow, "{}", s);
+            assert!(expr.distribution().is_none(), "{}", s);
+        }
+        let expr: Expr = "4294967295*4294967295".parse().unwrap();
+        assert!((expr.min(), expr.max()) == (i64::MAX, i64::MAX));
+
+        // A single exploding die can overflow before any dice are added up
+        let expr: Expr = "2d{9223372036854775807}!kh1".parse().unwrap();
+        assert!(expr.result(&mut MaxRng).unwrap_err() == EvalError::Overflow);
+        assert!(expr.distribution().is_none());
+    }
+
+    #[test]
+    fn labels_lead_with_the_total() {
+        let show = |s: &str| s.parse::<Repeat>().unwrap().result(&mut MaxRng).unwrap().to_string();
+        assert_eq!(show("Perception: 1d20+5 # passive"), "Perception: 25 ([20] + 5)");
+        assert_eq!(show("sword: 2d6"), "sword: 12 (6, 6)");
+        assert_eq!(show("nothing: 0d6"), "nothing: 0 (No dice)");
+        assert_eq!(show("hits: 2d6>5"), "hits: 6*, 6* (Successes: 2)");
+        assert_eq!(show("1d20+5 # passive"), "[20] + 5 (Total: 25)");
+    }
+
+    #[test]
+    fn named_faces_cant_be_calculated_with() {
+        let expr: Expr = "2d{hit,miss}+1".parse().unwrap();
+        assert!(expr.result(&mut MaxRng).unwrap_err() == EvalError::SymbolicArithmetic);
+
+        let expr: Expr = "2d{hit,miss}".parse().unwrap();
+        assert!(expr.result(&mut MaxRng).is_ok());
+    }
+
+    #[test]
+    fn exprs_know_their_range_and_average() {
+        let expr: Expr = "2d6-1d4".parse().unwrap();
+        assert_eq!((expr.min(), expr.max()), (-2, 11));
+        assert_eq!(expr.expected(), 4.5);
+
+        let expr: Expr = "-(1d6*2)".parse().unwrap();
+        assert_eq!((expr.min(), expr.max()), (-12, -2));
+
+        let expr: Expr = "12/(1d3-2)".parse().unwrap();
+        assert_eq!((expr.min(), expr.max()), (-12, 12));
+    }
+
+    #[test]
+    fn exprs_know_their_distribution() {
+        let dist = |s: &str| s.parse::<Expr>().unwrap().distribution();
+
+        let difference = dist("1d6-1d6").unwrap();
+        assert_eq!((difference.min(), difference.max(), difference.mode()), (-5, 5, 0));
+        assert_eq!(difference.at_least(1).to_string(), "5/12");
+        assert_eq!(difference.variance().to_string(), "35/6");
+
+        // Dividing by zero is left out
+        let quotient = dist("12/(1d3-2)").unwrap();
+        assert_eq!(quotient.iter().map(|(v, p)| (v, p.to_string())).collect::<Vec<_>>(), [(-12, "1/2".to_string()), (12, "1/2".to_string())]);
+        assert_eq!("12/(1d3-2)".parse::<Expr>().unwrap().expected(), 0.0);
+        assert!(dist("1d6/0").is_none());
+
+        // And so is how likely it is, through nested divisions too
+        let zero = |s: &str| s.parse::<Expr>().unwrap().division_by_zero().map(|p| p.to_string());
+        assert_eq!(zero("12/(1d3-2)").as_deref(), Some("1/3"));
+        assert_eq!(zero("1d6/(1d2-1)/(1d2-1)").as_deref(), Some("3/4"));
+        assert_eq!(zero("-(1d4/(1d4-1))+1d6").as_deref(), Some("1/4"));
+        assert_eq!(zero("1d6/2").as_deref(), Some("0"));
+        assert_eq!(zero("d{a,b}/0"), None);
+        assert!(dist("d{-9223372036854775808,1}/(1d3-2)").is_none());
+
+        assert!(dist("-(2d6)").unwrap().max() == -2);
+        assert!(dist("2d{hit,miss}+1").is_none());
+        assert!(dist("2d{hit,miss}").is_some());
+    }
+
+    #[test]
+    fn exprs_render_canonically() {
+        for s in &["2d6+1d4+3", "(1d8+2)*2", "1d4-(2-1)", "-(2d6)", "20", "(20)", "4d6kh3*-2"] {
+            let expr: Expr = s.parse().unwrap();
+            assert_eq!(expr.notation().parse::<Expr>(), Ok(expr), "for {:?}", s);
+        }
+        assert_eq!("(20)".parse::<Expr>().unwrap().notation(), "(20)");
+    }
+
+    #[test]
+    fn rejects_malformed_exprs() {
+        for s in &["", "2d6+", "(2d6", "2d6)", "2d6 3", "2*+3", "d"] {
+            assert!(s.parse::<Expr>().is_err(), "accepted {:?}", s);
+        }
+    }
+
+    #[test]
+    fn displays_the_whole_calculation() {
+        assert!(roll_max("2d6").to_string() == "6, 6 (Total: 12)");
+        assert!(roll_max("(1d8+2)*2").to_string() == "([8] + 2) * 2 (Total: 20)");
+        assert!(roll_max("1d4-(2-1)").to_string() == "[4] - (2 - 1) (Total: 3)");
+    }
+}
@@ -0,0 +1,266 @@
+//! Rolls as JSON, for `--format json` and, with the `serde` feature, for
+//! serde.
+//!
+//! Each result builds a `Json` value once, so the command line and serde
+//! always agree on the layout:
+//!
+//! - A die is `{"value", "rolls", "rerolled", "tens", "symbol", "kept",
+//!   "exploded", "success", "failure"}`, with `symbol` null unless the die
+//!   has named faces.
+//! - A roll is `{"dice", "successes", "tally", "total"}`, with `successes`
+//!   null unless it's a dice pool and `tally` null unless its faces are
+//!   named.
+//! - An expression is a tree of rolls, `{"number", "total"}`,
+//!   `{"negate", "total"}` and `{"op", "lhs", "rhs", "total"}`, so every
+//!   sub-expression carries its subtotal. A result rolled from a `Repeat`
+//!   with a label or comment adds `label` or `comment` at the top.
+
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::de::{self, Deserialize, Deserializer};
+#[cfg(feature = "serde")]
+use serde::ser::{Serialize, SerializeMap, SerializeSeq, Serializer};
+
+#[cfg(feature = "serde")]
+use expr::{Expr, ExprResult};
+#[cfg(feature = "serde")]
+use {DieRoll, RollCmd, RollResult};
+
+/// A JSON value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Str(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// An object with the given fields in order.
+    pub(crate) fn object(fields: Vec<(&str, Json)>) -> Json {
+        Json::Object(fields.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+    }
+
+    /// An array of numbers.
+    pub(crate) fn ints(values: &[i64]) -> Json {
+        Json::Array(values.iter().map(|&v| Json::Int(v)).collect())
+    }
+}
+
+impl<'a> From<&'a str> for Json {
+    fn from(s: &'a str) -> Json {
+        Json::Str(s.to_string())
+    }
+}
+
+impl<T: Into<Json>> From<Option<T>> for Json {
+    fn from(value: Option<T>) -> Json {
+        value.map_or(Json::Null, Into::into)
+    }
+}
+
+impl From<i64> for Json {
+    fn from(n: i64) -> Json {
+        Json::Int(n)
+    }
+}
+
+impl fmt::Display for Json {
+    /// Writes compact JSON.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Int(n) => write!(f, "{}", n),
+            Json::Str(ref s) => write_str(f, s),
+            Json::Array(ref values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(ref fields) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_str(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+/// Writes a quoted string, escaping what JSON requires.
+fn write_str(f: &mut fmt::Formatter, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Json {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match *self {
+            Json::Null => serializer.serialize_none(),
+            Json::Bool(b) => serializer.serialize_bool(b),
+            Json::Int(n) => serializer.serialize_i64(n),
+            Json::Str(ref s) => serializer.serialize_str(s),
+            Json::Array(ref values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            Json::Object(ref fields) => {
+                let mut map = serializer.serialize_map(Some(fields.len()))?;
+                for (key, value) in fields {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for DieRoll {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for RollResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for ExprResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json().serialize(serializer)
+    }
+}
+
+/// Serializes as its canonical notation.
+#[cfg(feature = "serde")]
+impl Serialize for RollCmd {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserializes from dice notation. Custom dice aren't known here, so they
+/// can't be deserialized.
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for RollCmd {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<RollCmd, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Serializes as its canonical notation.
+#[cfg(feature = "serde")]
+impl Serialize for Expr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserializes from dice notation, like `RollCmd`.
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Expr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Expr, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod json_tests {
+    use super::*;
+
+    #[test]
+    fn writes_compact_json() {
+        let json = Json::object(vec![
+            ("n", Json::Int(-3)),
+            ("s", "a \"b\"\n".into()),
+            ("list", Json::ints(&[1, 2])),
+            ("none", Json::from(None::<i64>)),
+            ("yes", Json::Bool(true)),
+        ]);
+        assert_eq!(json.to_string(), r#"{"n":-3,"s":"a \"b\"\n","list":[1,2],"none":null,"yes":true}"#);
+    }
+
+    #[test]
+    fn rolls_carry_every_die_and_subtotal() {
+        use expr::Expr;
+        use rng::Fixed;
+
+        let expr: Expr = "-(2d6kl1*3)".parse().unwrap();
+        let result = expr.result(&mut Fixed::new(vec![5, 2])).unwrap();
+        let die = |value, kept| format!(
+            r#"{{"value":{0},"rolls":[{0}],"rerolled":[],"tens":[],"symbol":null,"kept":{1},"exploded":false,"success":false,"failure":false}}"#,
+            value, kept
+        );
+        assert_eq!(result.to_json().to_string(), format!(
+            r#"{{"negate":{{"op":"*","lhs":{{"dice":[{},{}],"successes":null,"tally":null,"total":2}},"rhs":{{"number":3,"total":3}},"total":6}},"total":-6}}"#,
+            die(5, false), die(2, true)
+        ));
+    }
+
+    #[test]
+    fn labels_and_comments_top_the_result() {
+        use expr::Repeat;
+        use rng::MaxRng;
+
+        let repeat: Repeat = "hit: 1+2 # flanking".parse().unwrap();
+        let result = repeat.result(&mut MaxRng).unwrap();
+        assert_eq!(
+            result.to_json().to_string(),
+            r#"{"op":"+","lhs":{"number":1,"total":1},"rhs":{"number":2,"total":2},"total":3,"label":"hit","comment":"flanking"}"#
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_agrees_with_the_command_line() {
+        extern crate serde_json;
+        use expr::Repeat;
+        use rng::Fixed;
+
+        let expr: Expr = "4d6kh3+2".parse().unwrap();
+        let result = expr.result(&mut Fixed::new(vec![6, 1, 4, 4])).unwrap();
+        assert_eq!(serde_json::to_string(&result).unwrap(), result.to_json().to_string());
+        assert_eq!(serde_json::to_string(&expr).unwrap(), r#""4d6kh3+2""#);
+
+        let repeat: Repeat = "str: 4d6kh3 # point buy".parse().unwrap();
+        let result = repeat.result(&mut Fixed::new(vec![6, 1, 4, 4])).unwrap();
+        let json: serde_json::Value = serde_json::to_value(&result).unwrap();
+        assert_eq!((&json["label"], &json["comment"]), (&"str".into(), &"point buy".into()));
+        assert_eq!(serde_json::from_str::<RollCmd>(r#""d6kh1!r1""#).unwrap(), "1d6r=1!kh1".parse().unwrap());
+        assert!(serde_json::from_str::<RollCmd>(r#""2d""#).is_err());
+    }
+}
@@ -1,32 +1,9 @@
-extern crate rand;
 extern crate rcmd;
 
-use rand::{ OsRng, Rng };
-use rcmd::RollCmd;
-use std::error::Error;
+use rcmd::{ cli, Registry };
 
-fn main() {
-    // Attempt to retrieve randomness from OsRng
-    let mut rng = match OsRng::new() {
-        Ok(rng) => rng,
-        Err(e)  => {
-            println!("{}", e.description());
-            return;
-        }
-    };
-
-    //
-    let rolls: Vec<_> = std::env::args()
-        .filter_map(|arg| arg.parse::<RollCmd>().ok())
-        .map(|cmd| cmd.result(|max| rng.gen_range(0, max) + 1))
-        .collect();
+use std::process;
 
-    for roll in rolls { println!("{}", roll); }
-}
-
-/*
-// Shouldn't this be a datatype with a Rollable typeclass?
-// Not simpler in the small, but more flexible in the large
-fn roll_d(sides: u32) -> u32 { // Don't see many use cases for u64 dice, but change if you need it :shrug:
+fn main() {
+    process::exit(cli::run(std::env::args().skip(1), &Registry::new()));
 }
-*/
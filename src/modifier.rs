@@ -0,0 +1,563 @@
+//! Modifiers that change how the dice of a `RollCmd` are rolled and counted.
+
+use std::fmt;
+use std::ops::Range;
+
+use num_bigint::BigUint;
+use num_traits::One;
+
+use die::Die;
+use distribution::Distribution;
+use rng::DiceRng;
+use rollable::Rollable;
+
+/// Which dice of a roll count towards its total.
+///
+/// Asking to keep or drop more dice than were rolled simply keeps or drops
+/// all of them. Ties are broken by roll order.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Keep {
+    /// Keep the highest n dice (`kh`)
+    Highest(u32),
+    /// Keep the lowest n dice (`kl`)
+    Lowest(u32),
+    /// Drop the highest n dice (`dh`)
+    DropHighest(u32),
+    /// Drop the lowest n dice (`dl`)
+    DropLowest(u32),
+}
+
+impl Keep {
+    /// Works out which of the given values are dropped.
+    pub(crate) fn dropped(self, values: &[i64]) -> Vec<bool> {
+        let n = values.len();
+        let kept = self.kept(n);
+
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by_key(|&i| values[i]);
+
+        let mut dropped = vec![false; n];
+        for &i in order[..kept.start].iter().chain(&order[kept.end..]) {
+            dropped[i] = true;
+        }
+        dropped
+    }
+
+    /// The ranks of the dice kept out of n, counting from the lowest at 0.
+    pub(crate) fn kept(self, n: usize) -> Range<usize> {
+        let clamp = |k: u32| (k as usize).min(n);
+        match self {
+            Keep::Highest(k) => n - clamp(k)..n,
+            Keep::Lowest(k) => 0..clamp(k),
+            Keep::DropHighest(k) => 0..n - clamp(k),
+            Keep::DropLowest(k) => clamp(k)..n,
+        }
+    }
+}
+
+impl fmt::Display for Keep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Keep::Highest(n) => write!(f, "kh{}", n),
+            Keep::Lowest(n) => write!(f, "kl{}", n),
+            Keep::DropHighest(n) => write!(f, "dh{}", n),
+            Keep::DropLowest(n) => write!(f, "dl{}", n),
+        }
+    }
+}
+
+/// A target a die's value is compared against.
+///
+/// Following the usual dice roller convention, `>` and `<` include the
+/// target itself, so `>5` matches a 5 or a 6.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Compare {
+    /// Exactly n (`=n`)
+    Equal(i64),
+    /// n or more (`>n`)
+    AtLeast(i64),
+    /// n or less (`<n`)
+    AtMost(i64),
+}
+
+impl Compare {
+    /// Whether the value meets this target.
+    pub fn matches(self, value: i64) -> bool {
+        match self {
+            Compare::Equal(n) => value == n,
+            Compare::AtLeast(n) => value >= n,
+            Compare::AtMost(n) => value <= n,
+        }
+    }
+
+    /// Whether every face of the die meets this target.
+    pub fn matches_all(self, die: &Die) -> bool {
+        match self {
+            Compare::Equal(n) => die.min() == n && die.max() == n,
+            Compare::AtLeast(n) => n <= die.min(),
+            Compare::AtMost(n) => n >= die.max(),
+        }
+    }
+}
+
+impl fmt::Display for Compare {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Compare::Equal(n) => write!(f, "={}", n),
+            Compare::AtLeast(n) => write!(f, ">{}", n),
+            Compare::AtMost(n) => write!(f, "<{}", n),
+        }
+    }
+}
+
+/// How an exploding die adds its extra rolls.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ExplodeKind {
+    /// Each extra roll is added to the die and shown separately (`!`)
+    Standard,
+    /// Extra rolls are folded into a single value (`!!`)
+    Compound,
+    /// Like `Standard`, but every extra roll counts one less (`!p`)
+    Penetrate,
+}
+
+/// Rolls an extra die whenever a die meets the trigger.
+///
+/// The trigger defaults to the die's highest face. No die explodes more than
+/// `limit` times, so a `d1!` can't keep rolling forever.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Explode {
+    kind: ExplodeKind,
+    trigger: Option<Compare>,
+    limit: u32,
+}
+
+impl Explode {
+    /// The default number of times a single die may explode.
+    pub const DEFAULT_LIMIT: u32 = 100;
+
+    /// The most times `limit` lets a single die explode.
+    pub const MAX_LIMIT: u32 = 10_000;
+
+    /// The most explosions an exact distribution follows. Past this a die
+    /// is taken to stop exploding, as if this were its limit; a d6 gets this
+    /// far less than once in 10^15 rolls.
+    pub const DISTRIBUTION_LIMIT: u32 = 20;
+
+    // Construct a new Explode triggering on the highest face.
+    pub fn new(kind: ExplodeKind) -> Explode {
+        Explode { kind, trigger: None, limit: Explode::DEFAULT_LIMIT }
+    }
+
+    /// Explode on every value meeting the target instead of the highest face.
+    pub fn on(mut self, trigger: Compare) -> Explode {
+        self.trigger = Some(trigger);
+        self
+    }
+
+    /// Change how many times a single die may explode, up to `MAX_LIMIT`.
+    pub fn limit(mut self, limit: u32) -> Explode {
+        self.limit = limit.min(Explode::MAX_LIMIT);
+        self
+    }
+
+    /// How the extra rolls are added to the die.
+    pub fn kind(&self) -> ExplodeKind {
+        self.kind
+    }
+
+    /// Follows the chain of explosions starting from a die's first roll.
+    ///
+    /// Returns how much each roll in the chain contributes to the die.
+    pub(crate) fn roll<R: DiceRng + ?Sized>(&self, first: i64, die: &Die, rng: &mut R) -> Vec<i64> {
+        let trigger = self.trigger.unwrap_or(Compare::Equal(die.max()));
+        let mut last = first;
+        let mut rolls = vec![last];
+        while trigger.matches(last) && rolls.len() <= self.limit as usize {
+            last = die.roll_tens(rng).0;
+            rolls.push(match self.kind {
+                ExplodeKind::Penetrate => last.saturating_sub(1),
+                _ => last,
+            });
+        }
+        rolls
+    }
+
+    /// The trigger and the amount each extra roll adds for a given face.
+    fn rule(&self, die: &Die) -> (Compare, impl Fn(i64) -> i64) {
+        let penetrate = self.kind == ExplodeKind::Penetrate;
+        let trigger = self.trigger.unwrap_or(Compare::Equal(die.max()));
+        (trigger, move |v: i64| if penetrate { v.saturating_sub(1) } else { v })
+    }
+
+    /// The distribution of a die's value given the distribution of its
+    /// first roll and of any extra roll.
+    ///
+    /// Works backwards from the last explosion allowed, up to
+    /// `DISTRIBUTION_LIMIT`: `rest` is what the remaining chain adds once an
+    /// explosion is triggered.
+    pub(crate) fn pmf(&self, first: &Distribution, base: &Distribution, die: &Die) -> Distribution {
+        let (trigger, adds) = self.rule(die);
+        let chain = |pmf: &Distribution, rest: &Distribution, adds: &dyn Fn(i64) -> i64| {
+            let mut next = Vec::new();
+            for &(v, ref w) in pmf.weights() {
+                if trigger.matches(v) {
+                    next.extend(rest.weights().iter().map(|&(r, ref q)| (adds(v) + r, w * q)));
+                } else {
+                    next.push((adds(v), w * rest.total()));
+                }
+            }
+            Distribution::weighted(next)
+        };
+
+        let mut rest = Distribution::constant(0);
+        for _ in 0..self.distribution_depth() {
+            rest = chain(base, &rest, &adds);
+        }
+        chain(first, &rest, &|v| v)
+    }
+
+    /// How many times a die is rolled on average, given the chance of each
+    /// of the die's values.
+    pub(crate) fn expected_rolls(&self, chances: &[(i64, f64)], die: &Die) -> f64 {
+        let (trigger, _) = self.rule(die);
+        let p = chances.iter().filter(|&&(v, _)| trigger.matches(v)).map(|&(_, p)| p).sum();
+        chain_length(p, self.limit)
+    }
+
+    /// How many explosions an exact distribution follows.
+    pub(crate) fn distribution_depth(&self) -> u32 {
+        self.limit.min(Explode::DISTRIBUTION_LIMIT)
+    }
+
+    /// The expected value of a die, worked out like `pmf` but without
+    /// keeping track of every possible value.
+    pub(crate) fn expected(&self, first: &[(i64, f64)], base: &[(i64, f64)], die: &Die) -> f64 {
+        let (trigger, adds) = self.rule(die);
+        let chain = |pmf: &[(i64, f64)], rest: f64, adds: &dyn Fn(i64) -> i64| -> f64 {
+            pmf.iter()
+                .map(|&(v, p)| p * (adds(v) as f64 + if trigger.matches(v) { rest } else { 0.0 }))
+                .sum()
+        };
+
+        let mut rest = 0.0;
+        for _ in 0..self.limit {
+            let next = chain(base, rest, &adds);
+            // Further explosions are too unlikely to change anything
+            if next == rest {
+                break;
+            }
+            rest = next;
+        }
+        chain(first, rest, &|v| v)
+    }
+
+    /// The lowest and highest value a die can reach, given the faces it can
+    /// show, saturating where they'd overflow an `i64`.
+    pub(crate) fn bounds(&self, faces: &[i64], die: &Die) -> (i64, i64) {
+        let (trigger, adds) = self.rule(die);
+        let chain = |rest: (i64, i64), adds: &dyn Fn(i64) -> i64| {
+            let values = faces.iter().map(|&v| {
+                let (lo, hi) = if trigger.matches(v) { rest } else { (0, 0) };
+                (adds(v).saturating_add(lo), adds(v).saturating_add(hi))
+            });
+            values.fold((i64::MAX, i64::MIN), |(lo, hi), (l, h)| (lo.min(l), hi.max(h)))
+        };
+
+        let mut rest = (0, 0);
+        for _ in 0..self.limit {
+            let next = chain(rest, &adds);
+            // Saturated, or past every face that explodes
+            if next == rest {
+                break;
+            }
+            rest = next;
+        }
+        chain(rest, &|v| v)
+    }
+}
+
+impl fmt::Display for Explode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ExplodeKind::Standard => write!(f, "!")?,
+            ExplodeKind::Compound => write!(f, "!!")?,
+            ExplodeKind::Penetrate => write!(f, "!p")?,
+        }
+        match self.trigger {
+            Some(trigger) => write!(f, "{}", trigger),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Rerolls a die whose value meets the target.
+///
+/// By default the die is rerolled until it misses the target (`r`); a
+/// reroll-once modifier (`ro`) keeps whatever the second roll gives. A die
+/// is never rerolled more than `Reroll::LIMIT` times.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Reroll {
+    target: Compare,
+    once: bool,
+}
+
+impl Reroll {
+    /// The most times a single die is rerolled.
+    pub const LIMIT: u32 = 100;
+
+    // Construct a new Reroll that rerolls until the target is missed.
+    pub fn new(target: Compare) -> Reroll {
+        Reroll { target, once: false }
+    }
+
+    /// Only reroll a die once, keeping the second roll whatever it is.
+    pub fn once(mut self) -> Reroll {
+        self.once = true;
+        self
+    }
+
+    /// The values that get rerolled.
+    pub fn target(&self) -> Compare {
+        self.target
+    }
+
+    /// Whether a die is only rerolled once.
+    pub fn is_once(&self) -> bool {
+        self.once
+    }
+
+    /// Whether rerolling the die could never stop.
+    pub fn is_endless(&self, die: &Die) -> bool {
+        !self.once && self.target.matches_all(die)
+    }
+
+    /// Rerolls a die's first roll as needed.
+    ///
+    /// Returns the discarded values in order, along with the value kept.
+    pub(crate) fn roll<R: DiceRng + ?Sized>(&self, first: i64, die: &Die, rng: &mut R) -> (Vec<i64>, i64) {
+        let limit = if self.once { 1 } else { Reroll::LIMIT as usize };
+        let mut discarded = Vec::new();
+        let mut value = first;
+        while self.target.matches(value) && discarded.len() < limit {
+            discarded.push(value);
+            value = die.roll_tens(rng).0;
+        }
+        (discarded, value)
+    }
+
+    /// How many times a die is rolled on average, given the chance of each
+    /// of the die's values.
+    pub(crate) fn expected_rolls(&self, chances: &[(i64, f64)]) -> f64 {
+        let limit = if self.once { 1 } else { Reroll::LIMIT };
+        let p = chances.iter().filter(|&&(v, _)| self.target.matches(v)).map(|&(_, p)| p).sum();
+        chain_length(p, limit)
+    }
+
+    /// The distribution of a die's value after rerolling.
+    ///
+    /// A die stops on the first value that misses the target, unless every
+    /// roll allowed hits it, in which case the last roll stands whatever it
+    /// is. Out of a total weight t with h on the target, each of the L rolls
+    /// allowed is reached with chance (h/t)^i, so a value that misses gets
+    /// weight t (t^(L-1) + h t^(L-2) + ... + h^(L-1)) over t^(L+1), and every
+    /// value also gets h^L over t^(L+1) from the last roll.
+    pub(crate) fn pmf(&self, pmf: &Distribution) -> Distribution {
+        let limit = if self.once { 1 } else { Reroll::LIMIT as usize };
+        let total = pmf.total();
+        let hit: BigUint = pmf.weights().iter().filter(|&&(v, _)| self.target.matches(v)).map(|(_, w)| w).sum();
+        // Builds up the sum one term at a time, ending with hits at h^(L-1)
+        let (mut reached, mut hits) = (BigUint::one(), BigUint::one());
+        for _ in 1..limit {
+            hits *= &hit;
+            reached = reached * total + &hits;
+        }
+        let stuck = hits * &hit;
+        let missed = total * reached + &stuck;
+        Distribution::weighted(pmf.weights().iter().map(|&(v, ref w)| {
+            (v, if self.target.matches(v) { w * &stuck } else { w * &missed })
+        }))
+    }
+}
+
+impl fmt::Display for Reroll {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", if self.once { "ro" } else { "r" }, self.target)
+    }
+}
+
+/// Counts dice meeting a target instead of summing them.
+///
+/// Each kept die meeting the target is one success. Dice meeting the
+/// optional failure target subtract one, so a pool can come out negative.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Success {
+    target: Compare,
+    failure: Option<Compare>,
+}
+
+impl Success {
+    // Construct a new Success counting dice that meet the target.
+    pub fn new(target: Compare) -> Success {
+        Success { target, failure: None }
+    }
+
+    /// Subtract a success for every die meeting the failure target.
+    pub fn failure(mut self, failure: Compare) -> Success {
+        self.failure = Some(failure);
+        self
+    }
+
+    /// The values that count as a success.
+    pub fn target(&self) -> Compare {
+        self.target
+    }
+
+    /// Whether a value is a success.
+    pub fn is_success(&self, value: i64) -> bool {
+        self.target.matches(value)
+    }
+
+    /// Whether a value is a failure. A value that is also a success never
+    /// counts as a failure.
+    pub fn is_failure(&self, value: i64) -> bool {
+        !self.is_success(value) && self.failure.is_some_and(|f| f.matches(value))
+    }
+}
+
+impl fmt::Display for Success {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.target)?;
+        match self.failure {
+            Some(failure) => write!(f, "f{}", failure),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The expected number of rolls in a chain that goes on after each roll
+/// with chance `p`, for at most `limit` more rolls.
+fn chain_length(p: f64, limit: u32) -> f64 {
+    if p >= 1.0 {
+        f64::from(limit) + 1.0
+    } else {
+        (1.0 - p.powf(f64::from(limit) + 1.0)) / (1.0 - p)
+    }
+}
+
+#[cfg(test)]
+mod modifier_tests {
+    use super::*;
+    use rng::{Fixed, MaxRng};
+    use RollCmd;
+
+    const D6: Die = Die::Standard(6);
+
+    #[test]
+    fn keeps_and_drops_the_right_dice() {
+        let values = [3, 6, 1, 4];
+        assert_eq!(Keep::Highest(3).dropped(&values), [false, false, true, false]);
+        assert_eq!(Keep::Lowest(1).dropped(&values), [true, true, false, true]);
+        assert_eq!(Keep::DropHighest(1).dropped(&values), [false, true, false, false]);
+        assert_eq!(Keep::DropLowest(2).dropped(&values), [true, false, true, false]);
+    }
+
+    #[test]
+    fn clamps_to_the_number_of_dice() {
+        let values = [2, 5];
+        assert_eq!(Keep::Highest(3).dropped(&values), [false, false]);
+        assert_eq!(Keep::DropLowest(3).dropped(&values), [true, true]);
+    }
+
+    #[test]
+    fn breaks_ties_by_roll_order() {
+        let values = [4, 4, 4];
+        assert_eq!(Keep::Highest(1).dropped(&values), [true, true, false]);
+        assert_eq!(Keep::Lowest(1).dropped(&values), [false, true, true]);
+    }
+
+    #[test]
+    fn explodes_on_the_highest_face_by_default() {
+        let mut rolls = Fixed::new(vec![6, 2]);
+        let chain = Explode::new(ExplodeKind::Standard).roll(6, &D6, &mut rolls);
+        assert_eq!(chain, [6, 6, 2]);
+    }
+
+    #[test]
+    fn explodes_on_a_custom_trigger() {
+        let mut rolls = Fixed::new(vec![6, 4, 5]);
+        let explode = Explode::new(ExplodeKind::Standard).on(Compare::AtLeast(5));
+        assert_eq!(explode.roll(5, &D6, &mut rolls), [5, 6, 4]);
+    }
+
+    #[test]
+    fn penetrating_dice_lose_one_per_explosion() {
+        let mut rolls = Fixed::new(vec![6, 3]);
+        let chain = Explode::new(ExplodeKind::Penetrate).roll(6, &D6, &mut rolls);
+        assert_eq!(chain, [6, 5, 2]);
+    }
+
+    #[test]
+    fn explosions_stop_at_the_limit() {
+        let chain = Explode::new(ExplodeKind::Standard).roll(1, &Die::Standard(1), &mut MaxRng);
+        assert_eq!(chain.len(), 1 + Explode::DEFAULT_LIMIT as usize);
+
+        let chain = Explode::new(ExplodeKind::Compound).limit(3).roll(6, &D6, &mut MaxRng);
+        assert_eq!(chain, [6, 6, 6, 6]);
+
+        // A huge limit is capped, and working out the odds stops early
+        let explode = Explode::new(ExplodeKind::Standard).limit(u32::MAX);
+        assert_eq!(explode.limit, Explode::MAX_LIMIT);
+        let cmd = RollCmd::new(1, 6).explode(explode);
+        assert_eq!((cmd.min(), cmd.max()), (1, 6 * i64::from(Explode::MAX_LIMIT) + 6));
+        assert!((cmd.expected() - 4.2).abs() < 1e-9);
+    }
+
+    #[test]
+    fn explosion_bounds_saturate() {
+        let huge = Die::Faces(vec![i64::MAX]);
+        let explode = Explode::new(ExplodeKind::Standard);
+        assert_eq!(explode.bounds(&[i64::MAX], &huge), (i64::MAX, i64::MAX));
+
+        let cmd = RollCmd::from_die(1, huge).explode(explode);
+        assert!(cmd.distribution().is_none());
+    }
+
+    #[test]
+    fn rerolls_until_the_target_is_missed() {
+        let mut rolls = Fixed::new(vec![2, 1, 5]);
+        let reroll = Reroll::new(Compare::AtMost(2));
+        assert_eq!(reroll.roll(1, &D6, &mut rolls), (vec![1, 2, 1], 5));
+    }
+
+    #[test]
+    fn rerolls_once_keeps_the_second_roll() {
+        let mut rolls = Fixed::new(vec![2, 5]);
+        let reroll = Reroll::new(Compare::AtMost(2)).once();
+        assert_eq!(reroll.roll(1, &D6, &mut rolls), (vec![1], 2));
+        assert_eq!(reroll.roll(4, &D6, &mut Fixed::new(vec![])), (vec![], 4));
+    }
+
+    #[test]
+    fn spots_endless_rerolls() {
+        assert!(Reroll::new(Compare::Equal(1)).is_endless(&Die::Standard(1)));
+        assert!(Reroll::new(Compare::AtMost(6)).is_endless(&D6));
+        assert!(Reroll::new(Compare::AtLeast(1)).is_endless(&Die::Standard(20)));
+        assert!(Reroll::new(Compare::AtLeast(-1)).is_endless(&Die::Fudge));
+        assert!(!Reroll::new(Compare::AtMost(5)).is_endless(&D6));
+        assert!(!Reroll::new(Compare::AtLeast(0)).is_endless(&Die::Fudge));
+        assert!(!Reroll::new(Compare::Equal(1)).once().is_endless(&Die::Standard(1)));
+
+        let reroll = Reroll::new(Compare::AtLeast(1));
+        let (discarded, _) = reroll.roll(3, &D6, &mut MaxRng);
+        assert_eq!(discarded.len(), Reroll::LIMIT as usize);
+    }
+
+    #[test]
+    fn successes_win_over_failures() {
+        let success = Success::new(Compare::AtLeast(8)).failure(Compare::AtMost(8));
+        assert!(success.is_success(8) && !success.is_failure(8));
+        assert!(success.is_failure(1) && !success.is_success(1));
+        assert!(!Success::new(Compare::AtLeast(8)).is_failure(1));
+    }
+}
@@ -0,0 +1,1183 @@
+//! Tokenizer and recursive-descent parser for dice expressions.
+//!
+//! The grammar, loosest binding first:
+//!
+//! ```text
+//! line  := (label ':')? repeat ('#' comment)?
+//! repeat := (number 'x')? expr
+//! expr  := term (('+' | '-') term)*
+//! term  := unary (('*' | '/') unary)*
+//! unary := '-' unary | atom
+//! atom  := dice | number | '(' expr ')'
+//! dice  := number? die modifier*
+//! die   := 'd' number | 'd%' | 'dF' ('.' ('1' | '2'))? | 'd{' face (',' face)* '}' | 'd' name
+//! face  := '-'? number | name
+//! modifier := keep | explode | reroll | success | tens
+//! tens    := ('b' | 'p') number?
+//! keep    := ('kh' | 'kl' | 'dh' | 'dl') number?
+//! explode := '!' ('!' | 'p')? compare?
+//! reroll  := ('r' | 'ro') (number | compare)
+//! success := compare ('f' (number | compare))?
+//! compare := ('=' | '>' | '>=' | '<' | '<=') '-'? number
+//! ```
+//!
+//! A dice term is accepted only when it is written exactly as intended:
+//!
+//! - The count, `d` and sides are written together with no whitespace, so
+//!   `2d6` is a term but `2 d6` and `2d 6` are errors.
+//! - The `d` is always lowercase and must be followed directly by the number of
+//!   sides, a `%` or an uppercase `F`; `2d`, `2dd6`, `2dx6` and `4df` are
+//!   errors rather than some other die. `d%` is the same die as `d100`.
+//! - The count may be left out (`d6` is `1d6`) and may be zero; the number of
+//!   sides must be at least one.
+//! - Counts, sides and constants are decimal and must fit in a `u32`. The
+//!   `Registry` being parsed with further limits how many dice a term may
+//!   roll and how many sides they may have, counting bonus and penalty dice
+//!   towards the dice: `5000d%b1` rolls 10,000.
+//! - Modifiers follow the sides directly, may each appear only once, and
+//!   default to 1 when their number is left out (`2d20kh` is `2d20kh1`).
+//! - Comparisons include their target, so `>5` and `>=5` both mean "5 or
+//!   more". A bare number means exactly that value, so `r1` is `r=1`.
+//! - A reroll that every face would trigger, like `d1r1`, is an error.
+//! - A die's faces may be listed in braces. If every face is a number the
+//!   faces are summed like any other die, otherwise they are all names and
+//!   are tallied, so they take no modifiers. No face may be blank.
+//! - A `name` is a custom die from the `Registry` being parsed with, and is
+//!   matched before any of the built-in dice, so a registered `Fate` wins
+//!   over `dF`.
+//! - Bonus (`b`) and penalty (`p`) dice roll a d100 as tens and units dice,
+//!   so they are only accepted on a d100. `d%b0` is a plain d100 rolled that
+//!   way.
+//! - Nothing may follow a complete expression, so `d6d` is an error.
+//! - A count and `x` written together before an expression, as in
+//!   `6x4d6kh3`, roll it that many times over. Only a `Repeat` takes one,
+//!   the count must be at least one, and the `Registry` limits how many
+//!   times. The expression must roll dice, and a lone number isn't
+//!   shorthand for a die there, so a typo like `3x8` is an error.
+//! - A `Repeat` may also be labelled by any text up to the first `:`, as in
+//!   `sword: 1d8+3`, and commented by any text after a `#`, as in
+//!   `1d20+5 # Perception`. Both are trimmed, and the label can't be blank.
+//!   A `:` or `#` between braces is part of a face, as in `d{a:b,c}`.
+//!
+//! Whitespace is otherwise ignored. An expression consisting of nothing but a
+//! number is shorthand for a single die with that many sides, so `20` rolls a
+//! d20 just like it always has.
+
+use std::error::Error;
+use std::fmt;
+
+use die::{CustomDie, Die};
+use expr::{Expr, Op, Repeat};
+use {Compare, Explode, ExplodeKind, Keep, Reroll, RollCmd, Success};
+
+/// A byte range into the parsed input.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+impl Span {
+    fn new(start: usize, end: usize) -> Span {
+        Span { start, end }
+    }
+}
+
+/// An error raised while parsing a `RollCmd` or `Expr`.
+///
+/// Every variant carries the span of the offending input so it can be pointed
+/// out with `ParseError::render`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseError {
+    /// A character that can't start any token.
+    UnexpectedChar(char, Span),
+    /// A valid token in a place where it makes no sense, e.g. `2*+3`.
+    UnexpectedToken(Span),
+    /// The input stopped in the middle of an expression, e.g. `2d6+`.
+    UnexpectedEnd(Span),
+    /// A `d` that isn't followed by a number of sides, e.g. `2d`.
+    MissingSides(Span),
+    /// A die with no sides, e.g. `2d0`.
+    ZeroSides(Span),
+    /// A FATE die variant other than `dF.1` or `dF.2`, e.g. `dF.3`.
+    UnknownFudge(Span),
+    /// Bonus or penalty dice on something other than a d100, e.g. `d20b1`.
+    NotPercentile(Span),
+    /// A `{` without a matching `}`.
+    UnclosedBrace(Span),
+    /// A blank entry in a list of faces, e.g. `d{1,,3}`.
+    EmptyFace(Span),
+    /// A numbered face that doesn't fit in an `i64`.
+    FaceOverflow(Span),
+    /// A modifier on a die with named faces, e.g. `d{hit,miss}kh1`.
+    SymbolicModifier(Span),
+    /// A count, side count or constant that doesn't fit in a `u32`.
+    NumberOverflow(Span),
+    /// A comparison without a number to compare against, e.g. `d6!>`.
+    MissingTarget(Span),
+    /// The same kind of modifier given twice on one die, e.g. `4d6kh3kl1`.
+    DuplicateModifier(Span),
+    /// A reroll that every face of the die would trigger, e.g. `d1r1`.
+    EndlessReroll(Span),
+    /// More dice in one term than the `Registry` allows, which is given.
+    TooManyDice(u32, Span),
+    /// A die with more sides than the `Registry` allows, which is given.
+    TooManySides(u32, Span),
+    /// More repetitions than the `Registry` allows, which is given.
+    TooManyRepeats(u32, Span),
+    /// A roll repeated zero times, e.g. `0x2d6`.
+    ZeroRepeats(Span),
+    /// A repetition of something other than dice, e.g. `3x8`.
+    NothingToRepeat(Span),
+    /// A `(` without a matching `)`.
+    UnclosedParen(Span),
+    /// Input left over after a complete expression, e.g. the `)` in `2d6)`.
+    TrailingInput(Span),
+    /// A `:` with nothing before it to label the roll, e.g. `: 1d6`.
+    EmptyLabel(Span),
+}
+
+impl ParseError {
+    /// Returns the span of input this error refers to.
+    pub fn span(&self) -> Span {
+        match *self {
+            ParseError::UnexpectedChar(_, span)
+            | ParseError::UnexpectedToken(span)
+            | ParseError::UnexpectedEnd(span)
+            | ParseError::MissingSides(span)
+            | ParseError::ZeroSides(span)
+            | ParseError::UnknownFudge(span)
+            | ParseError::NotPercentile(span)
+            | ParseError::UnclosedBrace(span)
+            | ParseError::EmptyFace(span)
+            | ParseError::FaceOverflow(span)
+            | ParseError::SymbolicModifier(span)
+            | ParseError::NumberOverflow(span)
+            | ParseError::MissingTarget(span)
+            | ParseError::DuplicateModifier(span)
+            | ParseError::EndlessReroll(span)
+            | ParseError::TooManyDice(_, span)
+            | ParseError::TooManySides(_, span)
+            | ParseError::TooManyRepeats(_, span)
+            | ParseError::ZeroRepeats(span)
+            | ParseError::NothingToRepeat(span)
+            | ParseError::UnclosedParen(span)
+            | ParseError::TrailingInput(span)
+            | ParseError::EmptyLabel(span) => span,
+        }
+    }
+
+    /// Renders the error beneath the input it came from, with carets marking
+    /// the offending span.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::RollCmd;
+    /// let err = "2d6x3".parse::<RollCmd>().unwrap_err();
+    /// assert!(err.render("2d6x3") == "2d6x3\n   ^ Unexpected trailing input");
+    /// ```
+    pub fn render(&self, input: &str) -> String {
+        let span = self.span();
+        let column = input[..span.start].chars().count();
+        let width = input[span.start..span.end].chars().count().max(1);
+        format!("{}\n{}{} {}", input, " ".repeat(column), "^".repeat(width), self)
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ParseError::UnexpectedChar(c, _) => write!(f, "Unexpected character '{}'", c),
+            ParseError::UnexpectedToken(_) => write!(f, "Unexpected token"),
+            ParseError::UnexpectedEnd(_) => write!(f, "Unexpected end of input"),
+            ParseError::MissingSides(_) => write!(f, "Expected number of sides after 'd'"),
+            ParseError::ZeroSides(_) => write!(f, "Dice must have at least one side"),
+            ParseError::UnknownFudge(_) => write!(f, "Expected dF.1 or dF.2"),
+            ParseError::NotPercentile(_) => write!(f, "Bonus and penalty dice need a d100"),
+            ParseError::UnclosedBrace(_) => write!(f, "Unclosed '{{'"),
+            ParseError::EmptyFace(_) => write!(f, "Faces can't be blank"),
+            ParseError::FaceOverflow(_) => write!(f, "Face too large (maximum is {})", i64::MAX),
+            ParseError::SymbolicModifier(_) => write!(f, "Dice with named faces can't take modifiers"),
+            ParseError::NumberOverflow(_) => write!(f, "Number too large (maximum is {})", u32::MAX),
+            ParseError::MissingTarget(_) => write!(f, "Expected a number to compare against"),
+            ParseError::DuplicateModifier(_) => write!(f, "Modifier given more than once"),
+            ParseError::EndlessReroll(_) => write!(f, "Every face would be rerolled forever"),
+            ParseError::TooManyDice(max, _) => write!(f, "Too many dice (maximum is {})", max),
+            ParseError::TooManySides(max, _) => write!(f, "Too many sides (maximum is {})", max),
+            ParseError::TooManyRepeats(max, _) => write!(f, "Too many repetitions (maximum is {})", max),
+            ParseError::ZeroRepeats(_) => write!(f, "Rolls must be repeated at least once"),
+            ParseError::NothingToRepeat(_) => write!(f, "Expected dice to repeat"),
+            ParseError::UnclosedParen(_) => write!(f, "Unclosed '('"),
+            ParseError::TrailingInput(_) => write!(f, "Unexpected trailing input"),
+            ParseError::EmptyLabel(_) => write!(f, "Expected a label before ':'"),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Tok<'a> {
+    Num(u32),
+    Word(&'a str),
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    LParen,
+    RParen,
+    Bang,
+    Eq,
+    Lt,
+    Gt,
+    Dot,
+    Percent,
+    /// Everything between a `{` and its `}`
+    Faces(&'a str),
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Token<'a> {
+    tok: Tok<'a>,
+    span: Span,
+}
+
+/// Letter sequences read as words of their own even when written together,
+/// longest first, so `4dFkh1` is read as `4`, `dF`, `kh`, `1`. Any other run
+/// of letters is a single word.
+const KEYWORDS: &[&str] = &["dF", "kh", "kl", "dh", "dl", "ro", "b", "d", "f", "p", "r", "x"];
+
+/// Split the input from byte `from` on into tokens, skipping whitespace.
+/// Spans count from the start of `s`.
+fn tokenize(s: &str, from: usize) -> Result<Vec<Token<'_>>, ParseError> {
+    let mut toks = Vec::new();
+    let mut chars = s.char_indices().skip_while(|&(i, _)| i < from).peekable();
+
+    while let Some(&(start, c)) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+            continue;
+        }
+
+        let tok = match c {
+            '+' => Tok::Plus,
+            '-' => Tok::Minus,
+            '*' => Tok::Star,
+            '/' => Tok::Slash,
+            '(' => Tok::LParen,
+            ')' => Tok::RParen,
+            '!' => Tok::Bang,
+            '=' => Tok::Eq,
+            '<' => Tok::Lt,
+            '>' => Tok::Gt,
+            '.' => Tok::Dot,
+            '%' => Tok::Percent,
+            '{' => {
+                // Kept whole so that names like `blank` aren't split into keywords
+                let end = match s[start..].find('}') {
+                    Some(i) => start + i + 1,
+                    None => return Err(ParseError::UnclosedBrace(Span::new(start, start + 1))),
+                };
+                while chars.peek().is_some_and(|&(i, _)| i < end) {
+                    chars.next();
+                }
+                toks.push(Token { tok: Tok::Faces(&s[start + 1..end - 1]), span: Span::new(start, end) });
+                continue;
+            }
+            _ if c.is_ascii_digit() || c.is_alphabetic() => {
+                let rest = &s[start..];
+                let len = if c.is_ascii_digit() {
+                    rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len())
+                } else {
+                    match KEYWORDS.iter().find(|k| rest.starts_with(*k)) {
+                        Some(k) => k.len(),
+                        None => rest.find(|c: char| !c.is_alphabetic()).unwrap_or(rest.len()),
+                    }
+                };
+                while chars.peek().is_some_and(|&(i, _)| i < start + len) {
+                    chars.next();
+                }
+
+                let text = &rest[..len];
+                let span = Span::new(start, start + len);
+                let tok = if c.is_ascii_digit() {
+                    Tok::Num(text.parse().map_err(|_| ParseError::NumberOverflow(span))?)
+                } else {
+                    Tok::Word(text)
+                };
+                toks.push(Token { tok, span });
+                continue;
+            }
+            _ => return Err(ParseError::UnexpectedChar(c, Span::new(start, start + c.len_utf8()))),
+        };
+        toks.push(Token { tok, span: Span::new(start, start + c.len_utf8()) });
+        chars.next();
+    }
+
+    Ok(toks)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    toks: Vec<Token<'a>>,
+    pos: usize,
+    registry: &'a Registry,
+}
+
+impl<'a> Parser<'a> {
+    /// A parser for `s` from byte `from` on.
+    fn new(s: &'a str, from: usize, registry: &'a Registry) -> Result<Parser<'a>, ParseError> {
+        Ok(Parser { input: s, toks: tokenize(s, from)?, pos: 0, registry })
+    }
+
+    fn peek(&self) -> Option<Tok<'a>> {
+        self.toks.get(self.pos).map(|t| t.tok)
+    }
+
+    /// The span of the next token, or an empty span at the end of the input.
+    fn span(&self) -> Span {
+        let len = self.input.len();
+        self.toks.get(self.pos).map_or(Span::new(len, len), |t| t.span)
+    }
+
+    fn bump(&mut self) -> Option<Tok<'a>> {
+        let tok = self.peek();
+        self.pos += 1;
+        tok
+    }
+
+    /// An error for whatever token comes next.
+    fn unexpected(&self) -> ParseError {
+        match self.peek() {
+            Some(_) => ParseError::UnexpectedToken(self.span()),
+            None => ParseError::UnexpectedEnd(self.span()),
+        }
+    }
+
+    fn finish(&self) -> Result<(), ParseError> {
+        match self.peek() {
+            None => Ok(()),
+            Some(_) => Err(ParseError::TrailingInput(self.span())),
+        }
+    }
+
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(Tok::Plus) => Op::Add,
+                Some(Tok::Minus) => Op::Sub,
+                _ => return Ok(lhs),
+            };
+            self.bump();
+            lhs = Expr::BinOp(op, Box::new(lhs), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expr, ParseError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Some(Tok::Star) => Op::Mul,
+                Some(Tok::Slash) => Op::Div,
+                _ => return Ok(lhs),
+            };
+            self.bump();
+            lhs = Expr::BinOp(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParseError> {
+        if self.peek() == Some(Tok::Minus) {
+            self.bump();
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.atom()
+    }
+
+    fn atom(&mut self) -> Result<Expr, ParseError> {
+        if let Some(cmd) = self.roll()? {
+            return Ok(Expr::Roll(cmd));
+        }
+        match self.peek() {
+            Some(Tok::LParen) => {
+                let open = self.span();
+                self.bump();
+                let inner = self.expr()?;
+                match self.peek() {
+                    Some(Tok::RParen) => {
+                        self.bump();
+                        Ok(inner)
+                    }
+                    None => Err(ParseError::UnclosedParen(open)),
+                    Some(_) => Err(self.unexpected()),
+                }
+            }
+            Some(Tok::Num(n)) => {
+                self.bump();
+                Ok(Expr::Num(n))
+            }
+            _ => Err(self.unexpected()),
+        }
+    }
+
+    /// Whether the token `offset` places ahead starts a die.
+    fn at_die(&self, offset: usize) -> bool {
+        match self.toks.get(self.pos + offset) {
+            Some(&Token { tok: Tok::Word(w), span }) if w.starts_with('d') => {
+                let rest = &self.input[span.start + 1..];
+                w == "d" || w == "dF" || self.registry.dice.iter().any(|d| rest.starts_with(d.name()))
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether the token `offset` places ahead directly follows the one
+    /// before it, with no whitespace in between.
+    fn joined(&self, offset: usize) -> bool {
+        let i = self.pos + offset;
+        match (i.checked_sub(1).and_then(|j| self.toks.get(j)), self.toks.get(i)) {
+            (Some(prev), Some(next)) => prev.span.end == next.span.start,
+            _ => false,
+        }
+    }
+
+    /// The span of the next token if it directly follows the previous one,
+    /// otherwise the empty span just after the previous token.
+    fn gap(&self) -> Span {
+        if self.joined(0) {
+            return self.span();
+        }
+        let end = self.pos.checked_sub(1).map_or(0, |i| self.toks[i].span.end);
+        Span::new(end, end)
+    }
+
+    /// Parse a dice term if one starts at the next token.
+    fn roll(&mut self) -> Result<Option<RollCmd>, ParseError> {
+        let count = match self.peek() {
+            Some(Tok::Num(n)) if self.at_die(1) && self.joined(1) => {
+                self.check_count(n)?;
+                self.bump();
+                n
+            }
+            _ if self.at_die(0) => 1,
+            _ => return Ok(None),
+        };
+        self.dice(count).map(Some)
+    }
+
+    /// Parse the die half of a dice term and its modifiers; the count has
+    /// already been consumed by the caller.
+    fn dice(&mut self, count: u32) -> Result<RollCmd, ParseError> {
+        if let Some(die) = self.custom() {
+            if die.is_empty() {
+                return Err(ParseError::ZeroSides(self.toks[self.pos - 1].span));
+            }
+            return self.modifiers(RollCmd::from_die(count, die));
+        }
+
+        let fudge = match self.peek() {
+            Some(Tok::Word("d")) => false,
+            Some(Tok::Word("dF")) => true,
+            _ => return Err(self.unexpected()),
+        };
+        self.bump();
+
+        let die = if fudge {
+            self.fudge()?
+        } else {
+            match self.peek() {
+                Some(Tok::Percent) if self.joined(0) => {
+                    self.check_sides(100)?;
+                    self.bump();
+                    Die::Standard(100)
+                }
+                Some(Tok::Num(0)) if self.joined(0) => return Err(ParseError::ZeroSides(self.span())),
+                Some(Tok::Num(sides)) if self.joined(0) => {
+                    self.check_sides(sides)?;
+                    self.bump();
+                    Die::Standard(sides)
+                }
+                Some(Tok::Faces(list)) if self.joined(0) => {
+                    let span = self.span();
+                    self.bump();
+                    faces(list, span)?
+                }
+                _ => return Err(ParseError::MissingSides(self.gap())),
+            }
+        };
+
+        if !die.is_symbolic() {
+            return self.modifiers(RollCmd::from_die(count, die));
+        }
+        let start = self.pos;
+        let cmd = self.modifiers(RollCmd::from_die(count, die))?;
+        if self.pos > start {
+            let span = Span::new(self.toks[start].span.start, self.toks[self.pos - 1].span.end);
+            return Err(ParseError::SymbolicModifier(span));
+        }
+        Ok(cmd)
+    }
+
+    /// Fail if the next token, a count of `n` dice, is more than the registry
+    /// allows.
+    fn check_count(&self, n: u32) -> Result<(), ParseError> {
+        let max = self.registry.max_count;
+        if n > max { Err(ParseError::TooManyDice(max, self.span())) } else { Ok(()) }
+    }
+
+    /// Fail if the next token, a number of sides, is more than the registry
+    /// allows.
+    fn check_sides(&self, sides: u32) -> Result<(), ParseError> {
+        let max = self.registry.max_sides;
+        if sides > max { Err(ParseError::TooManySides(max, self.span())) } else { Ok(()) }
+    }
+
+    /// Parse a registered custom die, whose name follows the `d` of the next
+    /// token. The longest matching name wins.
+    fn custom(&mut self) -> Option<Die> {
+        let start = self.span().start + 1;
+        let rest = &self.input[start..];
+        let registry = self.registry;
+        let die = registry.dice.iter().filter(|d| rest.starts_with(d.name())).max_by_key(|d| d.name().len())?;
+
+        // The name may run into a modifier, as in `dZkh1`, so split the
+        // token it ends in and read the rest of that token again
+        let end = start + die.name().len();
+        let last = self.pos + self.toks[self.pos..].iter().position(|t| t.span.end >= end)?;
+        let span = self.toks[last].span;
+        let tail = tokenize(&self.input[..span.end], end).ok()?;
+        let head = Token { tok: Tok::Word(&self.input[span.start..end]), span: Span::new(span.start, end) };
+        self.toks.splice(last..=last, Some(head).into_iter().chain(tail));
+        self.pos = last + 1;
+        Some(Die::Custom(die.clone()))
+    }
+
+    /// Parse the variant written after a `dF`, if any.
+    fn fudge(&mut self) -> Result<Die, ParseError> {
+        if !(self.peek() == Some(Tok::Dot) && self.joined(0)) {
+            return Ok(Die::Fudge);
+        }
+        let start = self.span().start;
+        self.bump();
+        match self.joined_num() {
+            Some(1) => Ok(Die::FudgeOne),
+            Some(2) => Ok(Die::Fudge),
+            _ => Err(ParseError::UnknownFudge(Span::new(start, self.toks[self.pos - 1].span.end))),
+        }
+    }
+
+    /// Parse a number written directly after the previous token, if any.
+    fn joined_num(&mut self) -> Option<u32> {
+        match self.peek() {
+            Some(Tok::Num(n)) if self.joined(0) => {
+                self.bump();
+                Some(n)
+            }
+            _ => None,
+        }
+    }
+
+    /// Parse a comparison written directly after the previous token, if any.
+    fn compare(&mut self) -> Result<Option<Compare>, ParseError> {
+        let (target, inclusive): (fn(i64) -> Compare, bool) = match self.peek() {
+            Some(Tok::Eq) if self.joined(0) => (Compare::Equal, false),
+            Some(Tok::Gt) if self.joined(0) => (Compare::AtLeast, true),
+            Some(Tok::Lt) if self.joined(0) => (Compare::AtMost, true),
+            _ => return Ok(None),
+        };
+        self.bump();
+
+        // `>=` and `<=` are explicit spellings of `>` and `<`
+        if inclusive && self.peek() == Some(Tok::Eq) && self.joined(0) {
+            self.bump();
+        }
+        let negative = self.peek() == Some(Tok::Minus) && self.joined(0);
+        if negative {
+            self.bump();
+        }
+        match self.joined_num() {
+            Some(n) if negative => Ok(Some(target(-i64::from(n)))),
+            Some(n) => Ok(Some(target(i64::from(n)))),
+            None => Err(ParseError::MissingTarget(self.gap())),
+        }
+    }
+
+    /// Parse the target of a reroll or failure, where a bare number means
+    /// exactly that value.
+    fn target(&mut self) -> Result<Compare, ParseError> {
+        if let Some(n) = self.joined_num() {
+            return Ok(Compare::Equal(i64::from(n)));
+        }
+        self.compare()?.ok_or_else(|| ParseError::MissingTarget(self.gap()))
+    }
+
+    /// Parse the modifiers written directly after a dice term's sides.
+    fn modifiers(&mut self, mut cmd: RollCmd) -> Result<RollCmd, ParseError> {
+        while self.joined(0) {
+            let span = self.span();
+            match self.peek() {
+                Some(Tok::Eq) | Some(Tok::Gt) | Some(Tok::Lt) => {
+                    if cmd.success.is_some() {
+                        return Err(ParseError::DuplicateModifier(span));
+                    }
+                    let mut success = match self.compare()? {
+                        Some(target) => Success::new(target),
+                        None => break,
+                    };
+                    if self.peek() == Some(Tok::Word("f")) && self.joined(0) {
+                        self.bump();
+                        success = success.failure(self.target()?);
+                    }
+                    cmd.success = Some(success);
+                }
+                Some(Tok::Word(word)) if word == "r" || word == "ro" => {
+                    if cmd.reroll.is_some() {
+                        return Err(ParseError::DuplicateModifier(span));
+                    }
+                    self.bump();
+                    let target = self.target()?;
+                    let reroll = if word == "ro" { Reroll::new(target).once() } else { Reroll::new(target) };
+                    if reroll.is_endless(&cmd.die) {
+                        let end = self.toks[self.pos - 1].span.end;
+                        return Err(ParseError::EndlessReroll(Span::new(span.start, end)));
+                    }
+                    cmd.reroll = Some(reroll);
+                }
+                Some(Tok::Word(word)) if word == "b" || word == "p" => {
+                    if cmd.die.is_percentile() {
+                        return Err(ParseError::DuplicateModifier(span));
+                    }
+                    self.bump();
+                    let number = match self.peek() {
+                        Some(Tok::Num(_)) if self.joined(0) => self.span(),
+                        _ => span,
+                    };
+                    let extra = self.joined_num().unwrap_or(1);
+                    // Every die rolls its extra tens dice as well as itself
+                    let max = self.registry.max_count;
+                    match extra.checked_add(1).and_then(|rolls| cmd.count.checked_mul(rolls)) {
+                        Some(dice) if dice <= max => {}
+                        _ => return Err(ParseError::TooManyDice(max, number)),
+                    }
+                    let extra = i64::from(extra);
+                    if cmd.die != Die::Standard(100) {
+                        let end = self.toks[self.pos - 1].span.end;
+                        return Err(ParseError::NotPercentile(Span::new(span.start, end)));
+                    }
+                    cmd.die = Die::Percentile(if word == "p" { -extra } else { extra });
+                }
+                Some(Tok::Word(word)) => {
+                    let keep: fn(u32) -> Keep = match word {
+                        "kh" => Keep::Highest,
+                        "kl" => Keep::Lowest,
+                        "dh" => Keep::DropHighest,
+                        "dl" => Keep::DropLowest,
+                        _ => break,
+                    };
+                    if cmd.keep.is_some() {
+                        return Err(ParseError::DuplicateModifier(span));
+                    }
+                    self.bump();
+                    cmd.keep = Some(keep(self.joined_num().unwrap_or(1)));
+                }
+                Some(Tok::Bang) => {
+                    if cmd.explode.is_some() {
+                        return Err(ParseError::DuplicateModifier(span));
+                    }
+                    self.bump();
+                    let kind = match self.peek() {
+                        Some(Tok::Bang) if self.joined(0) => ExplodeKind::Compound,
+                        Some(Tok::Word("p")) if self.joined(0) => ExplodeKind::Penetrate,
+                        _ => ExplodeKind::Standard,
+                    };
+                    if kind != ExplodeKind::Standard {
+                        self.bump();
+                    }
+                    let mut explode = Explode::new(kind);
+                    if let Some(trigger) = self.compare()? {
+                        explode = explode.on(trigger);
+                    }
+                    cmd.explode = Some(explode);
+                }
+                _ => break,
+            }
+        }
+        Ok(cmd)
+    }
+
+    /// Parse how many times to repeat what follows, if it starts with a
+    /// count and an `x`.
+    fn times(&mut self) -> Result<Option<u32>, ParseError> {
+        let x = self.toks.get(self.pos + 1).map(|t| t.tok) == Some(Tok::Word("x"));
+        let times = match self.peek() {
+            Some(Tok::Num(n)) if x && self.joined(1) => n,
+            _ => return Ok(None),
+        };
+        let max = self.registry.max_repeats;
+        if times == 0 {
+            return Err(ParseError::ZeroRepeats(self.span()));
+        }
+        if times > max {
+            return Err(ParseError::TooManyRepeats(max, self.span()));
+        }
+        self.bump();
+        self.bump();
+        Ok(Some(times))
+    }
+
+    /// Parse a lone number as a single die with that many sides.
+    fn shorthand(&mut self) -> Result<Option<RollCmd>, ParseError> {
+        let lone = self.pos + 1 == self.toks.len();
+        match self.peek() {
+            Some(Tok::Num(0)) if lone => Err(ParseError::ZeroSides(self.span())),
+            Some(Tok::Num(sides)) if lone => {
+                self.check_sides(sides)?;
+                self.bump();
+                Ok(Some(RollCmd::new(1, sides)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Parse the list of faces between a custom die's braces, which cover `span`.
+fn faces(list: &str, span: Span) -> Result<Die, ParseError> {
+    let mut faces = Vec::new();
+    let mut offset = span.start + 1;
+    for face in list.split(',') {
+        let name = face.trim();
+        if name.is_empty() {
+            // Point at the `,` or `}` that ends the blank face
+            let end = offset + face.len();
+            return Err(ParseError::EmptyFace(Span::new(end, end + 1)));
+        }
+        let start = offset + face.find(name).unwrap_or(0);
+        faces.push((name, Span::new(start, start + name.len())));
+        offset += face.len() + 1;
+    }
+
+    let numeric = |name: &str| {
+        let digits = name.strip_prefix('-').unwrap_or(name);
+        !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit())
+    };
+    if !faces.iter().all(|&(name, _)| numeric(name)) {
+        return Ok(Die::Symbols(faces.iter().map(|&(name, _)| name.to_string()).collect()));
+    }
+    faces
+        .iter()
+        .map(|&(name, span)| name.parse().map_err(|_| ParseError::FaceOverflow(span)))
+        .collect::<Result<_, _>>()
+        .map(Die::Faces)
+}
+
+/// The position of the first `c` that isn't between braces, where it would
+/// be part of a face.
+pub(crate) fn outside_braces(s: &str, c: char) -> Option<usize> {
+    let mut braced = false;
+    for (i, ch) in s.char_indices() {
+        match ch {
+            '{' => braced = true,
+            '}' => braced = false,
+            _ if ch == c && !braced => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whether an expression has a dice term anywhere in it.
+fn rolls_dice(expr: &Expr) -> bool {
+    match *expr {
+        Expr::Roll(_) => true,
+        Expr::Num(_) => false,
+        Expr::Neg(ref inner) => rolls_dice(inner),
+        Expr::BinOp(_, ref lhs, ref rhs) => rolls_dice(lhs) || rolls_dice(rhs),
+    }
+}
+
+/// The custom dice a parser knows about, beyond the built-in ones, and how
+/// large a dice term or repetition it accepts.
+///
+/// `Expr` and `RollCmd` parse with an empty registry through `FromStr`; parse
+/// through a registry to use dice of your own or to change the limits.
+///
+/// The limits keep untrusted input from rolling more than it should, e.g.
+/// `4294967295d6` from a chat command. By default they keep the totals of
+/// the built-in dice well inside an `i64`, but listed faces can be as large
+/// as an `i64` allows, so a roll of them can still overflow; rolling an
+/// `Expr` reports that as `EvalError::Overflow`.
+///
+/// # Examples
+///
+/// ```
+/// use rcmd::{ParseError, Registry};
+/// let registry = Registry::new().max_count(100);
+/// assert!(registry.parse_expr("100d6").is_ok());
+/// assert!(matches!(registry.parse_expr("101d6"), Err(ParseError::TooManyDice(100, _))));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Registry {
+    dice: Vec<CustomDie>,
+    max_count: u32,
+    max_sides: u32,
+    max_repeats: u32,
+}
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry {
+            dice: Vec::new(),
+            max_count: Registry::DEFAULT_MAX_COUNT,
+            max_sides: Registry::DEFAULT_MAX_SIDES,
+            max_repeats: Registry::DEFAULT_MAX_REPEATS,
+        }
+    }
+}
+
+impl Registry {
+    /// How many dice a term may roll unless `max_count` says otherwise.
+    pub const DEFAULT_MAX_COUNT: u32 = 10_000;
+
+    /// How many sides a die may have unless `max_sides` says otherwise.
+    pub const DEFAULT_MAX_SIDES: u32 = 1_000_000;
+
+    /// How many times a `Repeat` may roll unless `max_repeats` says
+    /// otherwise.
+    pub const DEFAULT_MAX_REPEATS: u32 = 100;
+
+    // Construct a Registry with no custom dice and the default limits.
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Accept at most `max` dice in a term, e.g. `10d6` but not `11d6` for
+    /// 10.
+    pub fn max_count(mut self, max: u32) -> Registry {
+        self.max_count = max;
+        self
+    }
+
+    /// Accept dice of at most `max` sides, e.g. `d100` but not `d101` for
+    /// 100.
+    pub fn max_sides(mut self, max: u32) -> Registry {
+        self.max_sides = max;
+        self
+    }
+
+    /// Accept at most `max` repetitions, e.g. `6x4d6` but not `7x4d6` for
+    /// 6.
+    pub fn max_repeats(mut self, max: u32) -> Registry {
+        self.max_repeats = max;
+        self
+    }
+
+    /// Accept a custom die by its name, e.g. `3dZ` for a die named `Z`. A
+    /// later die replaces an earlier one of the same name.
+    pub fn with(mut self, die: CustomDie) -> Registry {
+        self.dice.retain(|d| d.name() != die.name());
+        self.dice.push(die);
+        self
+    }
+
+    /// Parse a full dice expression.
+    pub fn parse_expr(&self, s: &str) -> Result<Expr, ParseError> {
+        let mut p = Parser::new(s, 0, self)?;
+        if let Some(cmd) = p.shorthand()? {
+            return Ok(Expr::Roll(cmd));
+        }
+        let expr = p.expr()?;
+        p.finish()?;
+        Ok(expr)
+    }
+
+    /// Parse a dice expression with an optional count of repetitions in
+    /// front, such as `6x4d6kh3`, and an optional label and comment, such as
+    /// `stats: 6x4d6kh3 # point buy`.
+    pub fn parse_repeat(&self, s: &str) -> Result<Repeat, ParseError> {
+        let (s, comment) = match outside_braces(s, '#') {
+            Some(i) => (&s[..i], Some(s[i + 1..].trim())),
+            None => (s, None),
+        };
+        let (from, label) = match outside_braces(s, ':') {
+            Some(i) if s[..i].trim().is_empty() => return Err(ParseError::EmptyLabel(Span::new(i, i + 1))),
+            Some(i) => (i + 1, Some(s[..i].trim())),
+            None => (0, None),
+        };
+
+        let mut p = Parser::new(s, from, self)?;
+        let times = p.times()?;
+        let start = p.span().start;
+        let shorthand = if times.is_none() { p.shorthand()? } else { None };
+        let expr = match shorthand {
+            Some(cmd) => Expr::Roll(cmd),
+            None => {
+                let expr = p.expr()?;
+                p.finish()?;
+                expr
+            }
+        };
+        if times.is_some() && !rolls_dice(&expr) {
+            return Err(ParseError::NothingToRepeat(Span::new(start, p.toks[p.pos - 1].span.end)));
+        }
+        let mut repeat = Repeat::new(times.unwrap_or(1), expr);
+        if let Some(label) = label {
+            repeat = repeat.labelled(label);
+        }
+        match comment {
+            Some(comment) if !comment.is_empty() => Ok(repeat.commented(comment)),
+            _ => Ok(repeat),
+        }
+    }
+
+    /// Parse a single dice term such as `2d6`, `d6` or `6`.
+    pub fn parse_roll(&self, s: &str) -> Result<RollCmd, ParseError> {
+        let mut p = Parser::new(s, 0, self)?;
+        if let Some(cmd) = p.shorthand()? {
+            return Ok(cmd);
+        }
+        let cmd = match p.roll()? {
+            Some(cmd) => cmd,
+            None => {
+                // Point past a count that isn't followed by a `d`
+                if let Some(Tok::Num(_)) = p.peek() {
+                    p.bump();
+                }
+                return Err(p.unexpected());
+            }
+        };
+        p.finish()?;
+        Ok(cmd)
+    }
+}
+
+/// Parse a full dice expression.
+pub fn parse_expr(s: &str) -> Result<Expr, ParseError> {
+    Registry::new().parse_expr(s)
+}
+
+/// Parse a dice expression with an optional count of repetitions in front,
+/// and an optional label and comment.
+pub fn parse_repeat(s: &str) -> Result<Repeat, ParseError> {
+    Registry::new().parse_repeat(s)
+}
+
+/// Parse a single dice term such as `2d6`, `d6` or `6`.
+pub fn parse_roll(s: &str) -> Result<RollCmd, ParseError> {
+    Registry::new().parse_roll(s)
+}
+
+#[cfg(test)]
+mod parse_tests {
+    use super::*;
+
+    #[test]
+    fn errors_point_at_the_offending_input() {
+        let cases = [
+            ("2d6x3", ParseError::TrailingInput(Span::new(3, 4))),
+            ("2d6?", ParseError::UnexpectedChar('?', Span::new(3, 4))),
+            ("2d+1", ParseError::MissingSides(Span::new(2, 3))),
+            ("2d0", ParseError::ZeroSides(Span::new(2, 3))),
+            ("99999999999d6", ParseError::NumberOverflow(Span::new(0, 11))),
+            ("2d6+", ParseError::UnexpectedEnd(Span::new(4, 4))),
+            ("2*+3", ParseError::UnexpectedToken(Span::new(2, 3))),
+            ("(2d6+1", ParseError::UnclosedParen(Span::new(0, 1))),
+            ("2d6)", ParseError::TrailingInput(Span::new(3, 4))),
+        ];
+        for &(input, err) in &cases {
+            assert_eq!(parse_expr(input).unwrap_err(), err, "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn accepts_well_formed_dice_terms() {
+        let cases = [
+            ("2d6", RollCmd::new(2, 6)),
+            ("d6", RollCmd::new(1, 6)),
+            ("6", RollCmd::new(1, 6)),
+            ("0d6", RollCmd::new(0, 6)),
+            ("1d1", RollCmd::new(1, 1)),
+            ("02d06", RollCmd::new(2, 6)),
+            (" 2d6 ", RollCmd::new(2, 6)),
+            ("4d6kh3", RollCmd::new(4, 6).keep(Keep::Highest(3))),
+            ("2d20kl", RollCmd::new(2, 20).keep(Keep::Lowest(1))),
+            ("d6dh0", RollCmd::new(1, 6).keep(Keep::DropHighest(0))),
+            ("d6!", RollCmd::new(1, 6).explode(Explode::new(ExplodeKind::Standard))),
+            ("3d6!!<2", RollCmd::new(3, 6).explode(Explode::new(ExplodeKind::Compound).on(Compare::AtMost(2)))),
+            ("4d6!p=6kh3", RollCmd::new(4, 6)
+                .explode(Explode::new(ExplodeKind::Penetrate).on(Compare::Equal(6)))
+                .keep(Keep::Highest(3))),
+            ("2d6ro<2", RollCmd::new(2, 6).reroll(Reroll::new(Compare::AtMost(2)).once())),
+            ("d%", RollCmd::new(1, 100)),
+            ("d{-1, 0,1 }!", RollCmd::from_die(1, Die::Faces(vec![-1, 0, 1])).explode(Explode::new(ExplodeKind::Standard))),
+            ("2d{blank,1}", RollCmd::from_die(2, Die::Symbols(vec!["blank".to_string(), "1".to_string()]))),
+            ("2d%kh1", RollCmd::new(2, 100).keep(Keep::Highest(1))),
+            ("d%b", RollCmd::from_die(1, Die::Percentile(1))),
+            ("d100p2", RollCmd::from_die(1, Die::Percentile(-2))),
+            ("d%b0", RollCmd::from_die(1, Die::Percentile(0))),
+            ("5000d%p", RollCmd::from_die(5000, Die::Percentile(-1))),
+            ("4dF.1r=-1", RollCmd::from_die(4, Die::FudgeOne).reroll(Reroll::new(Compare::Equal(-1)))),
+            ("3d6!pkh2", RollCmd::new(3, 6)
+                .explode(Explode::new(ExplodeKind::Penetrate))
+                .keep(Keep::Highest(2))),
+            ("6d10!>8>=8f1", RollCmd::new(6, 10)
+                .explode(Explode::new(ExplodeKind::Standard).on(Compare::AtLeast(8)))
+                .success(Success::new(Compare::AtLeast(8)).failure(Compare::Equal(1)))),
+            ("6d10!>=8", RollCmd::new(6, 10)
+                .explode(Explode::new(ExplodeKind::Standard).on(Compare::AtLeast(8)))),
+            ("6d10!>8", RollCmd::new(6, 10)
+                .explode(Explode::new(ExplodeKind::Standard).on(Compare::AtLeast(8)))),
+            ("d20r1!", RollCmd::new(1, 20)
+                .reroll(Reroll::new(Compare::Equal(1)))
+                .explode(Explode::new(ExplodeKind::Standard))),
+        ];
+        for &(input, ref cmd) in &cases {
+            assert_eq!(parse_roll(input).as_ref(), Ok(cmd), "input {:?}", input);
+            assert_eq!(parse_expr(input), Ok(Expr::Roll(cmd.clone())));
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_dice_terms() {
+        let cases = [
+            ("", ParseError::UnexpectedEnd(Span::new(0, 0))),
+            ("d", ParseError::MissingSides(Span::new(1, 1))),
+            ("2d", ParseError::MissingSides(Span::new(2, 2))),
+            ("2d 6", ParseError::MissingSides(Span::new(2, 2))),
+            ("2dd6", ParseError::MissingSides(Span::new(2, 3))),
+            ("2dx6", ParseError::MissingSides(Span::new(2, 3))),
+            ("2d-6", ParseError::MissingSides(Span::new(2, 3))),
+            ("d6d", ParseError::TrailingInput(Span::new(2, 3))),
+            ("2 d6", ParseError::UnexpectedToken(Span::new(2, 3))),
+            ("D6", ParseError::UnexpectedToken(Span::new(0, 1))),
+            ("0", ParseError::ZeroSides(Span::new(0, 1))),
+            ("0d0", ParseError::ZeroSides(Span::new(2, 3))),
+            ("2d0", ParseError::ZeroSides(Span::new(2, 3))),
+            ("2d6+1", ParseError::TrailingInput(Span::new(3, 4))),
+            ("4d6kh3dl1", ParseError::DuplicateModifier(Span::new(6, 8))),
+            ("4d6 kh3", ParseError::TrailingInput(Span::new(4, 6))),
+            ("4d6kx3", ParseError::TrailingInput(Span::new(3, 5))),
+            ("d6!!!", ParseError::DuplicateModifier(Span::new(4, 5))),
+            ("d6!>", ParseError::MissingTarget(Span::new(4, 4))),
+            ("d6!>x", ParseError::MissingTarget(Span::new(4, 5))),
+            ("d6!=>5", ParseError::MissingTarget(Span::new(4, 5))),
+            ("2d6ro", ParseError::MissingTarget(Span::new(5, 5))),
+            ("d1r1", ParseError::EndlessReroll(Span::new(2, 4))),
+            ("4df", ParseError::MissingSides(Span::new(2, 3))),
+            ("4dF.3", ParseError::UnknownFudge(Span::new(3, 5))),
+            ("d%%", ParseError::TrailingInput(Span::new(2, 3))),
+            ("d20b1", ParseError::NotPercentile(Span::new(3, 5))),
+            ("d%b1p1", ParseError::DuplicateModifier(Span::new(4, 5))),
+            ("d{1,2", ParseError::UnclosedBrace(Span::new(1, 2))),
+            ("d{}", ParseError::EmptyFace(Span::new(2, 3))),
+            ("d{1,,3}", ParseError::EmptyFace(Span::new(4, 5))),
+            ("d{1, 99999999999999999999}", ParseError::FaceOverflow(Span::new(5, 25))),
+            ("d{hit,miss}kh1", ParseError::SymbolicModifier(Span::new(11, 14))),
+            ("d {1,2}", ParseError::MissingSides(Span::new(1, 1))),
+            ("4dF.", ParseError::UnknownFudge(Span::new(3, 4))),
+            ("dFr>-1", ParseError::EndlessReroll(Span::new(2, 6))),
+            ("d6r<6", ParseError::EndlessReroll(Span::new(2, 5))),
+            ("10d10>8f", ParseError::MissingTarget(Span::new(8, 8))),
+            ("10d10>8<2", ParseError::DuplicateModifier(Span::new(7, 8))),
+            ("4294967296d6", ParseError::NumberOverflow(Span::new(0, 10))),
+            ("2d4294967296", ParseError::NumberOverflow(Span::new(2, 12))),
+            ("4294967295d6", ParseError::TooManyDice(Registry::DEFAULT_MAX_COUNT, Span::new(0, 10))),
+            ("10001d6", ParseError::TooManyDice(Registry::DEFAULT_MAX_COUNT, Span::new(0, 5))),
+            ("2d1000001", ParseError::TooManySides(Registry::DEFAULT_MAX_SIDES, Span::new(2, 9))),
+            ("1000001", ParseError::TooManySides(Registry::DEFAULT_MAX_SIDES, Span::new(0, 7))),
+            ("d%b10001", ParseError::TooManyDice(Registry::DEFAULT_MAX_COUNT, Span::new(3, 8))),
+            ("10000d%b10000", ParseError::TooManyDice(Registry::DEFAULT_MAX_COUNT, Span::new(8, 13))),
+            ("5001d%p", ParseError::TooManyDice(Registry::DEFAULT_MAX_COUNT, Span::new(6, 7))),
+            ("2d%b4294967295", ParseError::TooManyDice(Registry::DEFAULT_MAX_COUNT, Span::new(4, 14))),
+        ];
+        for &(input, err) in &cases {
+            assert_eq!(parse_roll(input), Err(err), "input {:?}", input);
+        }
+    }
+
+    #[test]
+    fn dice_terms_in_exprs_follow_the_same_rules() {
+        for s in &["2d+1", "1+2dd6", "(d)", "3d6 + 2 d6", "2d 6 + 1", "1d6d"] {
+            assert!(parse_expr(s).is_err(), "accepted {:?}", s);
+        }
+        assert!(parse_expr("2 + d6").is_ok());
+    }
+
+    #[test]
+    fn limits_can_be_raised_and_lowered() {
+        let unlimited = Registry::new().max_count(u32::MAX).max_sides(u32::MAX);
+        assert_eq!(unlimited.parse_roll("4294967295d4294967295"), Ok(RollCmd::new(u32::MAX, u32::MAX)));
+
+        let strict = Registry::new().max_count(10).max_sides(20);
+        assert_eq!(strict.parse_roll("10d20"), Ok(RollCmd::new(10, 20)));
+        assert_eq!(strict.parse_expr("1+11d6"), Err(ParseError::TooManyDice(10, Span::new(2, 4))));
+        assert_eq!(strict.parse_expr("d100"), Err(ParseError::TooManySides(20, Span::new(1, 4))));
+        assert_eq!(strict.parse_expr("d%"), Err(ParseError::TooManySides(20, Span::new(1, 2))));
+    }
+
+    #[test]
+    fn repeats_come_before_the_expression() {
+        let repeat = parse_repeat("6x4d6kh3").unwrap();
+        assert_eq!((repeat.times(), repeat.expr()), (6, &"4d6kh3".parse::<Expr>().unwrap()));
+        assert_eq!(parse_repeat("6x4d6kh3").unwrap().to_string(), "6x4d6kh3");
+        assert_eq!(parse_repeat("2x(1d8+2)*2").unwrap().to_string(), "2x(1d8+2)*2");
+        assert_eq!(parse_repeat("1d20+5").unwrap().to_string(), "1d20+5");
+        assert_eq!(parse_repeat("101x1d6"), Err(ParseError::TooManyRepeats(100, Span::new(0, 3))));
+        assert!(parse_repeat("2d6x3").is_err());
+        assert!(parse_expr("6x4d6").is_err());
+
+        // Typos stay errors rather than becoming repetitions
+        assert_eq!(parse_repeat("3x8"), Err(ParseError::NothingToRepeat(Span::new(2, 3))));
+        assert_eq!(parse_repeat("3x(8+2)"), Err(ParseError::NothingToRepeat(Span::new(2, 7))));
+        assert_eq!(parse_repeat("0x2d6"), Err(ParseError::ZeroRepeats(Span::new(0, 1))));
+    }
+
+    #[test]
+    fn labels_and_comments_surround_a_repeat() {
+        let repeat = parse_repeat(" Perception check : 1d20+5 #  passive too ").unwrap();
+        assert_eq!((repeat.label(), repeat.comment()), (Some("Perception check"), Some("passive too")));
+        assert_eq!(repeat.to_string(), "Perception check: 1d20+5 # passive too");
+        assert_eq!(parse_repeat("stats: 6x4d6kh3").unwrap().to_string(), "stats: 6x4d6kh3");
+        assert_eq!(parse_repeat("20 #").unwrap().to_string(), "1d20");
+        assert_eq!(parse_repeat(" : 1d6"), Err(ParseError::EmptyLabel(Span::new(1, 2))));
+        assert_eq!(parse_repeat("sword: 2d # oops"), Err(ParseError::MissingSides(Span::new(9, 9))));
+        assert_eq!(parse_repeat("sword: 2d6+ # oops"), Err(ParseError::UnexpectedEnd(Span::new(12, 12))));
+        assert!(parse_expr("sword: 1d8+3").is_err());
+
+        let repeat = parse_repeat("d{a:b,c#d}").unwrap();
+        assert_eq!((repeat.label(), repeat.comment()), (None, None));
+        assert_eq!(repeat.expr(), &Expr::Roll(RollCmd::from_die(1, Die::Symbols(vec!["a:b".into(), "c#d".into()]))));
+        let repeat = parse_repeat("odd: d{a:b,c} # #1").unwrap();
+        assert_eq!((repeat.label(), repeat.comment()), (Some("odd"), Some("#1")));
+    }
+
+    #[test]
+    fn registered_dice_parse_by_name() {
+        let zero = CustomDie::new("Z", Die::Faces(vec![0, 1, 2]));
+        let fate = CustomDie::new("Fate", Die::Fudge);
+        let registry = Registry::new().with(zero.clone()).with(fate.clone());
+
+        let cmd = registry.parse_roll("3dZkh1").unwrap();
+        assert_eq!(cmd, RollCmd::from_die(3, Die::Custom(zero.clone())).keep(Keep::Highest(1)));
+        assert_eq!(registry.parse_roll("dFate"), Ok(RollCmd::from_die(1, Die::Custom(fate))));
+        assert_eq!(registry.parse_roll("4dF"), Ok(RollCmd::from_die(4, Die::Fudge)));
+
+        let registry = registry.with(CustomDie::new("Nil", Die::Standard(0)));
+        assert_eq!(registry.parse_expr("1+2dNil"), Err(ParseError::ZeroSides(Span::new(4, 7))));
+        assert!(registry.parse_expr("dZ+dZ").is_ok());
+
+        assert!(parse_roll("3dZ").is_err());
+    }
+
+    #[test]
+    fn renders_carets_under_the_span() {
+        let err = parse_expr("1d20 + 99999999999").unwrap_err();
+        assert_eq!(
+            err.render("1d20 + 99999999999"),
+            "1d20 + 99999999999\n       ^^^^^^^^^^^ Number too large (maximum is 4294967295)"
+        );
+
+        let err = parse_expr("2d6+").unwrap_err();
+        assert_eq!(err.render("2d6+"), "2d6+\n    ^ Unexpected end of input");
+    }
+}
@@ -1,92 +1,729 @@
+extern crate num_bigint;
+extern crate num_integer;
+extern crate num_rational;
+extern crate num_traits;
+extern crate rand;
+extern crate terminal_size;
+#[cfg(feature = "repl")]
+extern crate rustyline;
+#[cfg(feature = "serde")]
+extern crate serde;
+
 use std::str::FromStr;
 use std::fmt;
 
+use num_traits::ToPrimitive;
+
+pub mod cli;
+mod die;
+mod distribution;
+mod estimate;
+mod expr;
+mod json;
+mod modifier;
+mod parse;
+#[cfg(feature = "repl")]
+mod repl;
+mod rng;
+mod rollable;
+
+use json::Json;
+
+pub use die::{CustomDie, Die, DieError, Ladder};
+pub use distribution::Distribution;
+pub use estimate::{Estimate, Estimator, Odds};
+pub use expr::{EvalError, Expr, ExprResult, Op, Repeat};
+pub use modifier::{Compare, Explode, ExplodeKind, Keep, Reroll, Success};
+pub use parse::{ParseError, Registry, Span};
+pub use rng::{AverageRng, DiceRng, Fixed, MaxRng, MinRng, RandRng, SeededRng};
+pub use rollable::Rollable;
+
 /// Store roll parameters
 ///
 /// ** Parameters: **
 /// - Count: number of dice you want to roll
-/// - Sides: number of sides to each dice
-#[derive(Eq, PartialEq)]
+/// - Die: the kind of die rolled, usually a number of sides
+/// - Keep: which dice count towards the total, if not all of them
+/// - Explode: when a die rolls again and adds to itself
+/// - Reroll: which values get thrown away and rolled again
+/// - Success: counts dice meeting a target instead of summing them
+#[derive(Clone, Debug, Eq, PartialEq)]
 pub struct RollCmd {
     count: u32,
-    sides: u32,
+    die: Die,
+    keep: Option<Keep>,
+    explode: Option<Explode>,
+    reroll: Option<Reroll>,
+    success: Option<Success>,
 }
 
 impl RollCmd {
     // Construct a new RollCmd. Count, then Sides.
+    //
+    // Panics if there are no sides; see try_new.
     pub fn new(c: u32, s: u32) -> RollCmd {
-        RollCmd { count: c, sides: s }
+        RollCmd::from_die(c, Die::Standard(s))
+    }
+
+    /// Construct a new RollCmd, or fail if the dice have no sides.
+    ///
+    /// Rolling no dice is fine, and totals 0.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rcmd::{DieError, RollCmd};
+    /// assert!(RollCmd::try_new(3, 0) == Err(DieError::ZeroSides));
+    /// assert!(RollCmd::try_new(0, 6).is_ok());
+    /// ```
+    pub fn try_new(count: u32, sides: u32) -> Result<RollCmd, DieError> {
+        RollCmd::try_from_die(count, Die::Standard(sides))
+    }
+
+    /// Construct a new RollCmd rolling any kind of die.
+    ///
+    /// # Panics
+    ///
+    /// If the die has no faces; see `try_from_die`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rcmd::{Die, RollCmd};
+    /// let cmd = RollCmd::from_die(4, Die::Fudge);
+    /// assert!(cmd == "4dF".parse().unwrap());
+    /// ```
+    pub fn from_die(count: u32, die: Die) -> RollCmd {
+        match RollCmd::try_from_die(count, die) {
+            Ok(cmd) => cmd,
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    /// Construct a new RollCmd rolling any kind of die, or fail if the die
+    /// has no faces.
+    pub fn try_from_die(count: u32, die: Die) -> Result<RollCmd, DieError> {
+        if die.is_empty() {
+            return Err(DieError::ZeroSides);
+        }
+        Ok(RollCmd { count, die, keep: None, explode: None, reroll: None, success: None })
+    }
+
+    /// Only count some of the dice towards the total.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rcmd::{Keep, RollCmd};
+    /// let cmd = RollCmd::new(4, 6).keep(Keep::Highest(3));
+    /// assert!(cmd == "4d6kh3".parse().unwrap());
+    /// ```
+    pub fn keep(mut self, keep: Keep) -> RollCmd {
+        self.keep = Some(keep);
+        self
+    }
+
+    /// Make the dice explode.
+    ///
+    /// Keep and drop modifiers see each exploded die as a single value.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rcmd::{Compare, Explode, ExplodeKind, RollCmd};
+    /// let explode = Explode::new(ExplodeKind::Standard).on(Compare::AtLeast(5));
+    /// assert!(RollCmd::new(1, 6).explode(explode) == "d6!>5".parse().unwrap());
+    /// ```
+    pub fn explode(mut self, explode: Explode) -> RollCmd {
+        self.explode = Some(explode);
+        self
+    }
+
+    /// Reroll dice that land on certain values.
+    ///
+    /// Rerolls happen before explosions, so only the value a die keeps can
+    /// make it explode.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rcmd::{Compare, Reroll, RollCmd};
+    /// let reroll = Reroll::new(Compare::AtMost(2)).once();
+    /// assert!(RollCmd::new(2, 6).reroll(reroll) == "2d6ro<2".parse().unwrap());
+    /// ```
+    pub fn reroll(mut self, reroll: Reroll) -> RollCmd {
+        self.reroll = Some(reroll);
+        self
+    }
+
+    /// Turn the roll into a dice pool that counts successes.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rcmd::{Compare, RollCmd, Success};
+    /// let success = Success::new(Compare::AtLeast(8)).failure(Compare::Equal(1));
+    /// assert!(RollCmd::new(10, 10).success(success) == "10d10>=8f1".parse().unwrap());
+    /// ```
+    pub fn success(mut self, success: Success) -> RollCmd {
+        self.success = Some(success);
+        self
+    }
+
+    /// Roll a d100 as a tens die and a units die, the way percentile dice are
+    /// read at the table. Any other die is left alone.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use rcmd::RollCmd;
+    /// let cmd = RollCmd::new(1, 100).percentile();
+    /// assert!(cmd == "d%b0".parse().unwrap());
+    /// ```
+    pub fn percentile(mut self) -> RollCmd {
+        if self.die == Die::Standard(100) {
+            self.die = Die::Percentile(0);
+        }
+        self
     }
 
     /// Generates a new RollResult based on a RollCmd.
     ///
     /// Each RollCmd can be used repeatedly; this function will generate new
     /// RollResults each time.
-    /// Because this is a higher order function it's up to the caller to provide
-    /// an appropriate 'random value of range' function.
+    /// It's up to the caller to provide the randomness, as any `DiceRng`.
     ///
     /// # Examples
     ///
-    /// Here we provide result with a max function, returning the highest
-    /// possible value for each roll.
+    /// Here we provide result with `MaxRng`, returning the highest possible
+    /// value for each roll.
     /// ```
-    /// use rcmd::RollCmd;
+    /// use rcmd::{MaxRng, RollCmd};
     /// let cmd = RollCmd::new(2, 6);
-    /// let result = cmd.result(|max| max);
-    /// assert!([6, 6] == result.values());
+    /// let result = cmd.result(&mut MaxRng);
+    /// assert!(result.values() == [6, 6]);
     /// ```
-    pub fn result<F: FnMut(u32) -> u32>(&self, mut f: F) -> RollResult {
-        RollResult((0..self.count).map(|_| f(self.sides)).collect())
+    pub fn result<R: DiceRng + ?Sized>(&self, rng: &mut R) -> RollResult {
+        if self.die.is_symbolic() {
+            return self.tally(rng);
+        }
+
+        let compound = self.explode.map(|e| e.kind()) == Some(ExplodeKind::Compound);
+        let mut dice: Vec<DieRoll> = (0..self.count)
+            .map(|_| {
+                let (first, mut tens) = self.die.roll_tens(rng);
+                let (rerolled, first) = match self.reroll {
+                    Some(reroll) => reroll.roll(first, &self.die, rng),
+                    None => (Vec::new(), first),
+                };
+                // The discarded tens dice belong to the roll that was thrown away
+                if !rerolled.is_empty() {
+                    tens.clear();
+                }
+                let rolls = match self.explode {
+                    Some(explode) => explode.roll(first, &self.die, rng),
+                    None => vec![first],
+                };
+                DieRoll {
+                    rerolled,
+                    tens,
+                    compound,
+                    fudge: self.die.is_fudge(),
+                    percentile: self.die.is_percentile(),
+                    ..DieRoll::new(rolls)
+                }
+            })
+            .collect();
+
+        if let Some(keep) = self.keep {
+            let values: Vec<i64> = dice.iter().map(|d| d.value()).collect();
+            for (die, dropped) in dice.iter_mut().zip(keep.dropped(&values)) {
+                die.dropped = dropped;
+            }
+        }
+
+        if let Some(success) = self.success {
+            for die in dice.iter_mut().filter(|d| !d.dropped) {
+                let value = die.value();
+                die.success = success.is_success(value);
+                die.failure = success.is_failure(value);
+            }
+        }
+        RollResult { dice, pool: self.success.is_some(), faces: Vec::new() }
+    }
+
+    /// Rolls dice with named faces, which take no modifiers.
+    fn tally<R: DiceRng + ?Sized>(&self, rng: &mut R) -> RollResult {
+        let dice = (0..self.count)
+            .map(|_| {
+                let symbol = self.die.symbol(rng.roll_die(self.die.sides())).map(String::from);
+                DieRoll { symbol, ..DieRoll::new(vec![0]) }
+            })
+            .collect();
+
+        let mut faces: Vec<String> = Vec::new();
+        if let Die::Symbols(ref symbols) = self.die {
+            for symbol in symbols {
+                if !faces.contains(symbol) {
+                    faces.push(symbol.clone());
+                }
+            }
+        }
+        RollResult { dice, pool: false, faces }
+    }
+}
+
+impl RollCmd {
+    /// The distribution of a single die's value after any rerolls and
+    /// explosions, if the die's own distribution is known.
+    fn die_pmf(&self) -> Option<Distribution> {
+        let base = self.die.distribution()?;
+        let first = match self.reroll {
+            Some(reroll) => reroll.pmf(&base),
+            None => base.clone(),
+        };
+        Some(match self.explode {
+            Some(explode) => explode.pmf(&first, &base, &self.die),
+            None => first,
+        })
+    }
+
+    /// A rough count of the arithmetic working out the exact distribution
+    /// takes: keeping k of n dice with v values each takes about n^2 k v^2
+    /// steps. Summing them doubles up the dice, pairing every total of the
+    /// dice summed so far with every total of the dice doubled, and a total
+    /// of k dice takes about kv values.
+    pub(crate) fn exact_cost(&self) -> f64 {
+        let mut values = (i128::from(self.die.max()) - i128::from(self.die.min()) + 1).max(1) as f64;
+        if let Some(explode) = self.explode {
+            values *= f64::from(explode.distribution_depth() + 1);
+        }
+        let n = f64::from(self.count);
+        match self.keep {
+            Some(keep) => n * n * keep.kept(self.count as usize).len() as f64 * values * values,
+            None => {
+                let totals = |k: f64| k * (values - 1.0) + 1.0;
+                let (mut cost, mut summed, mut doubled, mut n) = (0.0, 0.0, 1.0, self.count);
+                while n > 0 {
+                    if n & 1 == 1 {
+                        cost += totals(summed) * totals(doubled);
+                        summed += doubled;
+                    }
+                    n >>= 1;
+                    if n > 0 {
+                        cost += totals(doubled) * totals(doubled);
+                        doubled *= 2.0;
+                    }
+                }
+                cost
+            }
+        }
+    }
+
+    /// How many dice rolling this takes on average, counting every reroll
+    /// and explosion.
+    pub(crate) fn dice_rolled(&self) -> f64 {
+        let n = f64::from(self.count);
+        let chances = match self.die.distribution() {
+            Some(ref dist) if self.reroll.is_some() || self.explode.is_some() => dist.to_f64(),
+            _ => return n,
+        };
+        let rerolls = self.reroll.map_or(1.0, |r| r.expected_rolls(&chances));
+        let explosions = self.explode.map_or(1.0, |e| e.expected_rolls(&chances, &self.die));
+        n * (rerolls + explosions - 1.0)
+    }
+
+    /// What a kept die with this value adds to the total.
+    fn score(&self, value: i64) -> i64 {
+        match self.success {
+            Some(success) if success.is_success(value) => 1,
+            Some(success) if success.is_failure(value) => -1,
+            Some(_) => 0,
+            None => value,
+        }
+    }
+
+    /// The lowest and highest total.
+    fn bounds(&self) -> (i64, i64) {
+        let ends = vec![self.die.min(), self.die.max()];
+        let faces: Vec<i64> = match (self.success, self.explode) {
+            // Only the ends matter, and a big die's distribution is costly
+            (None, None) => ends,
+            _ => self.die.distribution().map_or(ends, |dist| dist.values().collect()),
+        };
+
+        let values = match (self.success, self.explode) {
+            (Some(_), _) => match self.die_pmf() {
+                Some(pmf) => pmf.values().collect(),
+                None => faces,
+            },
+            (None, Some(explode)) => {
+                let (lo, hi) = explode.bounds(&faces, &self.die);
+                vec![lo, hi]
+            }
+            (None, None) => faces,
+        };
+        let scores = values.iter().map(|&v| self.score(v));
+        let (lo, hi) = scores.fold((i64::MAX, i64::MIN), |(lo, hi), s| (lo.min(s), hi.max(s)));
+
+        let n = self.count as usize;
+        let kept = self.keep.map_or(n, |keep| keep.kept(n).len()) as i64;
+        (kept.saturating_mul(lo), kept.saturating_mul(hi))
+    }
+}
+
+impl Rollable for RollCmd {
+    type Output = RollResult;
+
+    fn roll(&self, rng: &mut dyn DiceRng) -> RollResult {
+        self.result(rng)
+    }
+
+    fn min(&self) -> i64 {
+        self.bounds().0
+    }
+
+    fn max(&self) -> i64 {
+        self.bounds().1
+    }
+
+    /// Exact up to floating point, except that it's `NaN` for a custom die
+    /// that doesn't report its distribution but has modifiers.
+    fn expected(&self) -> f64 {
+        let n = self.count;
+        if self.keep.is_none() && self.success.is_none() {
+            if self.reroll.is_none() && self.explode.is_none() {
+                return f64::from(n) * self.die.expected();
+            }
+            let base = match self.die.distribution() {
+                Some(base) => base,
+                None => return f64::NAN,
+            };
+            let first = match self.reroll {
+                Some(reroll) => reroll.pmf(&base),
+                None => base.clone(),
+            };
+            let each = match self.explode {
+                Some(explode) => explode.expected(&first.to_f64(), &base.to_f64(), &self.die),
+                None => first.mean().to_f64().unwrap_or(f64::NAN),
+            };
+            return f64::from(n) * each;
+        }
+
+        let pmf = match self.die_pmf() {
+            Some(pmf) => pmf.to_f64(),
+            None => return f64::NAN,
+        };
+        let score = |v: i64| self.score(v) as f64;
+        match self.keep {
+            Some(keep) => rollable::kept_expected(&pmf, n, keep.kept(n as usize), score),
+            None => f64::from(n) * pmf.iter().map(|&(v, p)| score(v) * p).sum::<f64>(),
+        }
+    }
+
+    fn notation(&self) -> String {
+        self.to_string()
+    }
+
+    /// Exact, for any die whose own distribution is known. An exploding die
+    /// is followed as far as its explosion limit or
+    /// `Explode::DISTRIBUTION_LIMIT`, whichever is lower.
+    fn distribution(&self) -> Option<Distribution> {
+        // The bounds saturate where a total could overflow
+        let (lo, hi) = self.bounds();
+        if lo == i64::MIN || hi == i64::MAX {
+            return None;
+        }
+        let pmf = self.die_pmf()?;
+        match self.keep {
+            Some(keep) => Some(pmf.kept(self.count, keep.kept(self.count as usize), |v| self.score(v))),
+            None => pmf.map(|v| self.score(v)).sum(self.count),
+        }
+    }
+}
+
+impl fmt::Display for RollCmd {
+    /// Writes the command in canonical notation: the count, the die, then
+    /// any success, reroll, explode and keep modifiers in that order.
+    ///
+    /// # Examples
+    /// ```
+    /// use rcmd::RollCmd;
+    /// let cmd: RollCmd = "d6kh1!r1".parse().unwrap();
+    /// assert!(cmd.to_string() == "1d6r=1!kh1");
+    /// ```
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.count, self.die)?;
+        if let Some(success) = self.success {
+            write!(f, "{}", success)?;
+        }
+        if let Some(reroll) = self.reroll {
+            write!(f, "{}", reroll)?;
+        }
+        if let Some(explode) = self.explode {
+            write!(f, "{}", explode)?;
+        }
+        if let Some(keep) = self.keep {
+            write!(f, "{}", keep)?;
+        }
+        Ok(())
     }
 }
 
 impl FromStr for RollCmd {
-    type Err = String;
+    type Err = ParseError;
 
     /// Convert a string to a Result with a RollCmd struct.
+    ///
+    /// Only a single dice term such as `2d6`, `d6` or `6` is accepted; use
+    /// `Expr` for arithmetic. Malformed terms like `2d` or `2dd6` are errors
+    /// rather than being read as some other die.
     fn from_str(s: &str) -> Result<RollCmd, <RollCmd as FromStr>::Err> {
-        let split: Vec<u32> = s.split('d').filter_map(|n| n.parse().ok()).collect();
-        // Based on number of items grabbed by split, Ok(RollCmd) or Err
-        match split.len() {
-        // Could do this cleaner with a slice pattern, but that would require nightly :\
-            2 => {
-                let (count, sides) = (split[0], split[1]);
-                Ok(RollCmd::new(count, sides))
-            }
-            1 => { 
-                let sides = split[0];
-                Ok(RollCmd::new(1, sides))
-            }
-            _ => Err(format!("Invalid RollCmd: {}", s))
+        parse::parse_roll(s)
+    }
+}
+
+/// A single die within a RollResult.
+///
+/// An exploding die keeps the whole chain of rolls that made it up, and a
+/// rerolled die keeps the values it threw away.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DieRoll {
+    rolls: Vec<i64>,
+    rerolled: Vec<i64>,
+    tens: Vec<i64>,
+    dropped: bool,
+    compound: bool,
+    fudge: bool,
+    percentile: bool,
+    symbol: Option<String>,
+    success: bool,
+    failure: bool,
+}
+
+impl DieRoll {
+    fn new(rolls: Vec<i64>) -> DieRoll {
+        DieRoll {
+            rolls,
+            rerolled: Vec::new(),
+            tens: Vec::new(),
+            dropped: false,
+            compound: false,
+            fudge: false,
+            percentile: false,
+            symbol: None,
+            success: false,
+            failure: false,
+        }
+    }
+
+    /// The value of the die, including any explosions, saturating at the
+    /// bounds of an `i64` if it doesn't fit. See `checked_value`.
+    pub fn value(&self) -> i64 {
+        self.rolls.iter().fold(0i64, |sum, &roll| sum.saturating_add(roll))
+    }
+
+    /// The value of the die, or `None` if it doesn't fit in an `i64`.
+    pub fn checked_value(&self) -> Option<i64> {
+        self.rolls.iter().try_fold(0i64, |sum, &roll| sum.checked_add(roll))
+    }
+
+    /// What each roll in the die's chain contributed, starting with the
+    /// original roll. Penetrating rolls are already reduced by one.
+    pub fn rolls(&self) -> &[i64] {
+        &self.rolls
+    }
+
+    /// The values a reroll modifier threw away before the die settled, in
+    /// the order they were rolled.
+    pub fn rerolled(&self) -> &[i64] {
+        &self.rerolled
+    }
+
+    /// The tens dice that a percentile die's bonus or penalty dice threw
+    /// away, e.g. `[70]` for a bonus die showing 70 beside a kept 40.
+    pub fn tens(&self) -> &[i64] {
+        &self.tens
+    }
+
+    /// The face a die with named faces landed on.
+    pub fn symbol(&self) -> Option<&str> {
+        self.symbol.as_deref()
+    }
+
+    /// Whether the die met a dice pool's success target.
+    pub fn is_success(&self) -> bool {
+        self.success
+    }
+
+    /// Whether the die met a dice pool's failure target.
+    pub fn is_failure(&self) -> bool {
+        self.failure
+    }
+
+    /// Whether the die exploded at least once.
+    pub fn is_exploded(&self) -> bool {
+        self.rolls.len() > 1
+    }
+
+    /// Whether a keep or drop modifier excluded this die from the total.
+    pub fn is_dropped(&self) -> bool {
+        self.dropped
+    }
+
+    /// The die as JSON; see the `json` module for the layout.
+    pub(crate) fn to_json(&self) -> Json {
+        Json::object(vec![
+            ("value", Json::Int(self.value())),
+            ("rolls", Json::ints(self.rolls())),
+            ("rerolled", Json::ints(self.rerolled())),
+            ("tens", Json::ints(self.tens())),
+            ("symbol", self.symbol().into()),
+            ("kept", Json::Bool(!self.is_dropped())),
+            ("exploded", Json::Bool(self.is_exploded())),
+            ("success", Json::Bool(self.is_success())),
+            ("failure", Json::Bool(self.is_failure())),
+        ])
+    }
+}
+
+impl fmt::Display for DieRoll {
+    /// Exploded dice show their chain, e.g. `6+6+2`, unless they compound.
+    /// Rerolled dice lead with the values they discarded, e.g. `1->2->5`.
+    /// Successes are starred, e.g. `8*`, and failures marked, e.g. `1-`.
+    /// Dropped dice are struck through, e.g. `~1~`. FATE dice show `+`, `-`
+    /// or `0` rather than a number. Percentile dice show their tens and units
+    /// dice, e.g. `47 (40+7)`, and any tens dice they discarded, e.g.
+    /// `47 (40+7, ~70~)`. Named faces show their name.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(ref symbol) = self.symbol {
+            return write!(f, "{}", symbol);
+        }
+
+        let show = |n: i64| match n {
+            1 if self.fudge => "+".to_string(),
+            -1 if self.fudge => "-".to_string(),
+            100 if self.percentile => "100 (00+0)".to_string(),
+            _ if self.percentile => format!("{} ({:02}+{})", n, n / 10 * 10, n % 10),
+            _ => n.to_string(),
+        };
+
+        let mut value: String = self.rerolled.iter().map(|&n| show(n) + "->").collect();
+        if self.compound {
+            value += &show(self.value());
+        } else {
+            let as_strings: Vec<_> = self.rolls.iter().map(|&n| show(n)).collect();
+            value += &as_strings.join("+");
+        }
+        if !self.tens.is_empty() {
+            let tens: Vec<_> = self.tens.iter().map(|t| format!("~{:02}~", t)).collect();
+            value.insert_str(value.len() - 1, &format!(", {}", tens.join(", ")));
+        }
+        if self.success {
+            value += "*";
+        } else if self.failure {
+            value += "-";
+        }
+
+        if self.dropped {
+            write!(f, "~{}~", value)
+        } else {
+            write!(f, "{}", value)
         }
-        
     }
 }
 
-/// A vector of u32 representing the result of a RollCmd.
+/// A vector of DieRolls representing the result of a RollCmd.
 ///
 /// RollResult allows us to provide specialized function impementations for
 /// dealing with roll results.
-pub struct RollResult(Vec<u32>);
+#[derive(Debug)]
+pub struct RollResult {
+    dice: Vec<DieRoll>,
+    /// Whether the roll counts successes rather than summing
+    pool: bool,
+    /// The distinct named faces of the die, if its faces are named
+    faces: Vec<String>,
+}
 
 impl RollResult {
     /// Returns an iterator over the result of a roll.
     ///
-    /// Basically returns an iterator on the underlying vector.
-    pub fn iter<'a>(&'a self) -> std::slice::Iter<'a, u32> {
-        self.0.iter()
+    /// Basically returns an iterator on the underlying vector, so dropped dice
+    /// are included.
+    pub fn iter(&self) -> std::slice::Iter<'_, DieRoll> {
+        self.dice.iter()
     }
 
-    pub fn total(&self) -> u32 { // maybe change to u64?
-        // TODO: Does this repeat RollResult::iter?
-        self.0.iter().fold(0, |a, b| a + b)
+    /// Returns an iterator over the values of the dice that weren't dropped.
+    pub fn kept(&self) -> impl Iterator<Item = i64> + '_ {
+        self.iter().filter(|d| !d.dropped).map(|d| d.value())
     }
 
-    /// Returns the individual rolls as a slice.
+    /// Sums the dice that weren't dropped.
     ///
-    /// Basically unwraps the RollResult into it's underlying Vec<u32>
-    pub fn values(&self) -> &[u32] {
-        &self.0
+    /// Totals are `i64`s, since FATE dice, penalties and negative faces can
+    /// take them below zero. A sum that doesn't fit, which takes huge listed
+    /// faces or more dice or sides than a `Registry` allows by default,
+    /// saturates at the bounds of an `i64`. See `checked_sum`.
+    pub fn sum(&self) -> i64 {
+        let rolls = self.iter().filter(|d| !d.dropped).flat_map(|d| d.rolls.iter());
+        rolls.fold(0i64, |sum, &roll| sum.saturating_add(roll))
+    }
+
+    /// Sums the dice that weren't dropped, or returns `None` if the sum
+    /// doesn't fit in an `i64`.
+    pub fn checked_sum(&self) -> Option<i64> {
+        let mut rolls = self.iter().filter(|d| !d.dropped).flat_map(|d| d.rolls.iter());
+        rolls.try_fold(0i64, |sum, &roll| sum.checked_add(roll))
+    }
+
+    /// Counts successes minus failures if the roll is a dice pool.
+    pub fn successes(&self) -> Option<i64> {
+        if !self.pool {
+            return None;
+        }
+        Some(self.iter().map(|d| if d.success { 1 } else if d.failure { -1 } else { 0 }).sum())
+    }
+
+    /// Counts how many dice landed on each named face, in the order the die
+    /// lists them, if the die's faces are named.
+    pub fn tally(&self) -> Option<Vec<(&str, usize)>> {
+        if self.faces.is_empty() {
+            return None;
+        }
+        let count = |face: &str| self.iter().filter(|d| d.symbol() == Some(face)).count();
+        Some(self.faces.iter().map(|face| (face.as_str(), count(face))).collect())
+    }
+
+    /// The value of the roll: the number of successes for a dice pool,
+    /// otherwise the sum of the kept dice, saturating like `sum`. Named faces
+    /// have no value, so a roll of them totals 0; see `tally`.
+    pub fn total(&self) -> i64 {
+        self.successes().unwrap_or_else(|| self.sum())
+    }
+
+    /// Like `total`, but returns `None` if the sum doesn't fit in an `i64`.
+    pub fn checked_total(&self) -> Option<i64> {
+        self.successes().or_else(|| self.checked_sum())
+    }
+
+    /// Returns the value of every die rolled, including dropped ones.
+    pub fn values(&self) -> Vec<i64> {
+        self.iter().map(|d| d.value()).collect()
+    }
+
+    /// The roll as JSON; see the `json` module for the layout.
+    pub(crate) fn to_json(&self) -> Json {
+        let tally = self.tally().map(|tally| {
+            Json::Object(tally.into_iter().map(|(face, n)| (face.to_string(), Json::Int(n as i64))).collect())
+        });
+        Json::object(vec![
+            ("dice", Json::Array(self.iter().map(DieRoll::to_json).collect())),
+            ("successes", self.successes().into()),
+            ("tally", tally.into()),
+            ("total", Json::Int(self.total())),
+        ])
     }
 }
 
@@ -95,13 +732,31 @@ impl fmt::Display for RollResult {
     ///
     /// # Examples
     /// ```
-    /// use rcmd::RollResult;
-    /// let result = RollResult(vec![2, 3, 3]);
+    /// use rcmd::{Fixed, RollCmd};
+    /// let mut rolls = Fixed::new(vec![2, 3, 3]);
+    /// let result = RollCmd::new(3, 6).result(&mut rolls);
     /// assert!(result.to_string() == "2, 3, 3 (Total: 8)");
+    ///
+    /// let mut rolls = Fixed::new(vec![2, 6, 3, 1]);
+    /// let cmd: RollCmd = "4d6dl1".parse().unwrap();
+    /// let result = cmd.result(&mut rolls);
+    /// assert!(result.to_string() == "2, 6, 3, ~1~ (Total: 11)");
+    ///
+    /// let result = RollCmd::new(0, 6).result(&mut rolls);
+    /// assert!(result.to_string() == "No dice (Total: 0)");
     /// ```
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        let as_strings: Vec<_> = self.iter().map(|n| n.to_string()).collect();
-        write!(f, "{} (Total: {})", as_strings.join(", "), self.total())
+        let as_strings: Vec<_> = self.iter().map(|d| d.to_string()).collect();
+        let dice = if as_strings.is_empty() { "No dice".to_string() } else { as_strings.join(", ") };
+        if let Some(tally) = self.tally() {
+            let counts: Vec<_> = tally.iter().map(|&(face, n)| format!("{}: {}", face, n)).collect();
+            return write!(f, "{} ({})", dice, counts.join(", "));
+        }
+        match (self.successes(), self.checked_sum()) {
+            (Some(successes), _) => write!(f, "{} (Successes: {})", dice, successes),
+            (None, Some(sum)) => write!(f, "{} (Total: {})", dice, sum),
+            (None, None) => write!(f, "{} (Total too large)", dice),
+        }
     }
 }
 
@@ -121,4 +776,342 @@ mod rollcmd_tests {
         let cmd = RollCmd::new(1, 6);
         assert!(cmd == "6".parse().unwrap());
     }
+
+    #[test]
+    fn can_parse_keep_modifiers() {
+        assert!(RollCmd::new(4, 6).keep(Keep::Highest(3)) == "4d6kh3".parse().unwrap());
+        assert!(RollCmd::new(2, 20).keep(Keep::Lowest(1)) == "2d20kl1".parse().unwrap());
+        assert!(RollCmd::new(2, 20).keep(Keep::Highest(1)) == "2d20kh".parse().unwrap());
+        assert!(RollCmd::new(4, 6).keep(Keep::DropLowest(1)) == "4d6dl1".parse().unwrap());
+        assert!(RollCmd::new(3, 8).keep(Keep::DropHighest(2)) == "3d8dh2".parse().unwrap());
+    }
+
+    // Result tests
+    #[test]
+    fn dropped_dice_dont_count() {
+        let mut rolls = Fixed::new(vec![5, 2, 6, 2]);
+        let result = RollCmd::new(4, 6).keep(Keep::Highest(3)).result(&mut rolls);
+        assert!(result.total() == 13);
+        assert!(result.values() == [5, 2, 6, 2]);
+        assert!(result.kept().collect::<Vec<_>>() == [5, 6, 2]);
+    }
+
+    #[test]
+    fn huge_totals_saturate_rather_than_panic() {
+        let cmd: RollCmd = "2d{9223372036854775807}".parse().unwrap();
+        let result = cmd.result(&mut MaxRng);
+        assert_eq!((result.checked_total(), result.total()), (None, i64::MAX));
+        assert_eq!(result.to_string(), "9223372036854775807, 9223372036854775807 (Total too large)");
+
+        let cmd: RollCmd = "d{9223372036854775807}!".parse().unwrap();
+        let die = cmd.result(&mut MaxRng).iter().next().cloned().unwrap();
+        assert_eq!((die.checked_value(), die.value()), (None, i64::MAX));
+    }
+
+    #[test]
+    fn can_parse_explode_modifiers() {
+        let explode = |kind| RollCmd::new(3, 6).explode(Explode::new(kind));
+        assert!(explode(ExplodeKind::Standard) == "3d6!".parse().unwrap());
+        assert!(explode(ExplodeKind::Compound) == "3d6!!".parse().unwrap());
+        assert!(explode(ExplodeKind::Penetrate) == "3d6!p".parse().unwrap());
+
+        let cmd = RollCmd::new(1, 6).explode(Explode::new(ExplodeKind::Standard).on(Compare::AtLeast(5)));
+        assert!(cmd == "d6!>5".parse().unwrap());
+        assert!(cmd == "d6!>=5".parse().unwrap());
+    }
+
+    #[test]
+    fn exploded_dice_keep_their_chain() {
+        let mut rolls = Fixed::new(vec![6, 6, 2, 3]);
+        let cmd: RollCmd = "2d6!".parse().unwrap();
+        let result = cmd.result(&mut rolls);
+        assert!(result.total() == 17);
+        assert!(result.iter().next().unwrap().rolls() == [6, 6, 2]);
+        assert!(result.to_string() == "6+6+2, 3 (Total: 17)");
+
+        let mut rolls = Fixed::new(vec![6, 6, 2, 3]);
+        let cmd: RollCmd = "2d6!!".parse().unwrap();
+        let result = cmd.result(&mut rolls);
+        assert!(result.to_string() == "14, 3 (Total: 17)");
+    }
+
+    #[test]
+    fn keep_sees_exploded_dice_as_one() {
+        let mut rolls = Fixed::new(vec![6, 1, 5]);
+        let cmd: RollCmd = "2d6!kh1".parse().unwrap();
+        let result = cmd.result(&mut rolls);
+        assert!(result.to_string() == "6+1, ~5~ (Total: 7)");
+    }
+
+    #[test]
+    fn can_parse_reroll_modifiers() {
+        let reroll = |target| RollCmd::new(2, 6).reroll(Reroll::new(target));
+        assert!(reroll(Compare::Equal(1)) == "2d6r1".parse().unwrap());
+        assert!(reroll(Compare::Equal(1)) == "2d6r=1".parse().unwrap());
+        assert!(reroll(Compare::AtMost(2)) == "2d6r<2".parse().unwrap());
+        assert!(reroll(Compare::AtLeast(5)) == "2d6r>5".parse().unwrap());
+
+        let cmd = RollCmd::new(2, 6).reroll(Reroll::new(Compare::AtMost(2)).once());
+        assert!(cmd == "2d6ro<2".parse().unwrap());
+    }
+
+    #[test]
+    fn rerolled_dice_keep_what_they_discarded() {
+        let mut rolls = Fixed::new(vec![1, 2, 5, 4]);
+        let cmd: RollCmd = "2d6r<2".parse().unwrap();
+        let result = cmd.result(&mut rolls);
+        assert!(result.total() == 9);
+        assert!(result.iter().next().unwrap().rerolled() == [1, 2]);
+        assert!(result.to_string() == "1->2->5, 4 (Total: 9)");
+
+        let mut rolls = Fixed::new(vec![1, 2, 4]);
+        let cmd: RollCmd = "2d6ro<2".parse().unwrap();
+        let result = cmd.result(&mut rolls);
+        assert!(result.to_string() == "1->2, 4 (Total: 6)");
+    }
+
+    #[test]
+    fn rejects_endless_rerolls() {
+        for s in &["d1r1", "d6r<6", "d6r>1", "d20r>=1"] {
+            assert!(s.parse::<RollCmd>().is_err(), "accepted {:?}", s);
+        }
+        assert!("d1ro1".parse::<RollCmd>().is_ok());
+    }
+
+    #[test]
+    fn can_parse_success_modifiers() {
+        let pool = |success| RollCmd::new(10, 10).success(success);
+        assert!(pool(Success::new(Compare::AtLeast(8))) == "10d10>=8".parse().unwrap());
+        assert!(pool(Success::new(Compare::AtLeast(8))) == "10d10>8".parse().unwrap());
+        assert!(pool(Success::new(Compare::Equal(10))) == "10d10=10".parse().unwrap());
+        assert!(pool(Success::new(Compare::AtLeast(8)).failure(Compare::Equal(1))) == "10d10>8f1".parse().unwrap());
+        assert!(pool(Success::new(Compare::AtLeast(8)).failure(Compare::AtMost(2))) == "10d10>8f<2".parse().unwrap());
+    }
+
+    #[test]
+    fn pools_count_successes() {
+        let mut rolls = Fixed::new(vec![9, 3, 8, 1, 10]);
+        let cmd: RollCmd = "5d10>=8f1".parse().unwrap();
+        let result = cmd.result(&mut rolls);
+        assert!(result.sum() == 31);
+        assert!(result.successes() == Some(2));
+        assert!(result.total() == 2);
+        assert!(result.to_string() == "9*, 3, 8*, 1-, 10* (Successes: 2)");
+
+        let mut rolls = Fixed::new(vec![1, 1, 5]);
+        let cmd: RollCmd = "3d10>=8f1".parse().unwrap();
+        assert!(cmd.result(&mut rolls).total() == -2);
+    }
+
+    #[test]
+    fn dropped_dice_are_not_successes() {
+        let mut rolls = Fixed::new(vec![9, 8]);
+        let cmd: RollCmd = "2d10kl1>8".parse().unwrap();
+        let result = cmd.result(&mut rolls);
+        assert!(result.successes() == Some(1));
+        assert!(RollCmd::new(2, 6).result(&mut MaxRng).successes().is_none());
+    }
+
+    #[test]
+    fn can_parse_fudge_dice() {
+        assert!(RollCmd::from_die(4, Die::Fudge) == "4dF".parse().unwrap());
+        assert!(RollCmd::from_die(4, Die::Fudge) == "4dF.2".parse().unwrap());
+        assert!(RollCmd::from_die(1, Die::FudgeOne) == "dF.1".parse().unwrap());
+        assert!(RollCmd::from_die(4, Die::Fudge).keep(Keep::Highest(2)) == "4dFkh2".parse().unwrap());
+    }
+
+    #[test]
+    fn fudge_dice_show_symbols() {
+        let mut rolls = Fixed::new(vec![3, 1, 2, 3]);
+        let cmd: RollCmd = "4dF".parse().unwrap();
+        let result = cmd.result(&mut rolls);
+        assert!(result.values() == [1, -1, 0, 1]);
+        assert!(result.to_string() == "+, -, 0, + (Total: 1)");
+
+        let mut rolls = Fixed::new(vec![1, 1, 1, 1]);
+        assert!(cmd.result(&mut rolls).total() == -4);
+    }
+
+    #[test]
+    fn can_parse_custom_dice() {
+        let fibonacci = Die::Faces(vec![1, 1, 2, 3, 5, 8]);
+        assert!(RollCmd::from_die(2, fibonacci) == "2d{1,1,2,3,5,8}".parse().unwrap());
+
+        let symbols = vec!["hit".to_string(), "hit".to_string(), "miss".to_string()];
+        assert!(RollCmd::from_die(1, Die::Symbols(symbols)) == "d{hit, hit, miss}".parse().unwrap());
+    }
+
+    #[test]
+    fn custom_dice_sum_their_faces() {
+        let mut rolls = Fixed::new(vec![1, 4]);
+        let cmd: RollCmd = "2d{-1,0,0,1}".parse().unwrap();
+        let result = cmd.result(&mut rolls);
+        assert_eq!(result.to_string(), "-1, 1 (Total: 0)");
+    }
+
+    #[test]
+    fn symbolic_dice_are_tallied() {
+        let mut rolls = Fixed::new(vec![1, 4, 2]);
+        let cmd: RollCmd = "3d{hit,hit,miss,crit}".parse().unwrap();
+        let result = cmd.result(&mut rolls);
+        assert_eq!(result.to_string(), "hit, crit, hit (hit: 2, miss: 0, crit: 1)");
+        assert_eq!(result.tally().unwrap(), [("hit", 2), ("miss", 0), ("crit", 1)]);
+        assert_eq!(result.total(), 0);
+    }
+
+    #[test]
+    fn percentile_dice_show_tens_and_units() {
+        let mut rolls = Fixed::new(vec![5, 8, 1, 1]);
+        let result = RollCmd::new(2, 100).percentile().result(&mut rolls);
+        assert_eq!(result.to_string(), "47 (40+7), 100 (00+0) (Total: 147)");
+    }
+
+    #[test]
+    fn bonus_dice_show_the_tens_they_discarded() {
+        let mut rolls = Fixed::new(vec![8, 5, 3]);
+        let cmd: RollCmd = "d%b1".parse().unwrap();
+        let result = cmd.result(&mut rolls);
+        assert_eq!(result.to_string(), "42 (40+2, ~70~) (Total: 42)");
+        assert_eq!(result.iter().next().unwrap().tens(), [70]);
+    }
+
+    #[test]
+    fn rollcmds_know_their_range_and_average() {
+        let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+        let cmd: RollCmd = "3d6".parse().unwrap();
+        assert_eq!((cmd.min(), cmd.max()), (3, 18));
+        assert!(close(cmd.expected(), 10.5));
+
+        let cmd: RollCmd = "4d6kh3".parse().unwrap();
+        assert_eq!((cmd.min(), cmd.max()), (3, 18));
+        assert!(close(cmd.expected(), 15869.0 / 1296.0));
+
+        // A d6 that rerolls 1s once averages (1/6 * 3.5) + (5/6 * 4)
+        let cmd: RollCmd = "d6ro1".parse().unwrap();
+        assert!(close(cmd.expected(), 3.5 / 6.0 + 4.0 * 5.0 / 6.0));
+
+        // Exploding adds a sixth of a die on every six: 3.5 * 6/5, near enough
+        let cmd: RollCmd = "d6!".parse().unwrap();
+        assert_eq!((cmd.min(), cmd.max()), (1, 606));
+        assert!(close(cmd.expected(), 4.2));
+
+        let cmd: RollCmd = "d6!p".parse().unwrap();
+        assert_eq!(cmd.max(), 506);
+
+        let cmd: RollCmd = "4d10>8f1".parse().unwrap();
+        assert_eq!((cmd.min(), cmd.max()), (-4, 4));
+        assert!(close(cmd.expected(), 0.8));
+    }
+
+    #[test]
+    fn rollcmds_know_their_distribution() {
+        let dist = |s: &str| s.parse::<RollCmd>().unwrap().distribution().unwrap();
+        let ratio = |n: u64, d: u64| Distribution::from_weights(vec![(1, n), (0, d - n)]).chance(1);
+
+        let fours = dist("4d6kh3");
+        assert_eq!(fours.mean(), dist("4d6dl1").mean());
+        assert!((fours.mean().to_f64().unwrap() - 15869.0 / 1296.0).abs() < 1e-12);
+        assert_eq!((fours.chance(18), fours.at_least(15)), (ratio(21, 1296), ratio(300, 1296)));
+
+        assert_eq!(dist("d6ro1").chance(1), ratio(1, 36));
+        assert_eq!(dist("d6ro1").chance(6), ratio(7, 36));
+        // Only a hundred 1s in a row leave a 1 standing
+        assert_eq!(dist("d6r1").chance(1).to_string(), format!("1/{}", num_traits::pow(num_bigint::BigUint::from(6u32), 101)));
+
+        let exploded = dist("d6!");
+        assert_eq!((exploded.min(), exploded.max()), (1, 126));
+        assert_eq!((exploded.chance(6), exploded.chance(7)), (ratio(0, 1), ratio(1, 36)));
+        assert_eq!(dist("d6!p").chance(6), ratio(1, 36));
+
+        let pool = dist("4d10>8f1");
+        assert_eq!((pool.min(), pool.max()), (-4, 4));
+        assert_eq!(pool.mean(), ratio(4, 5));
+        assert_eq!(pool.chance(4), ratio(81, 10000));
+
+        assert_eq!(dist("0d6"), Distribution::constant(0));
+        let cmd = RollCmd::from_die(2, Die::Custom(CustomDie::new("Z", Die::Faces(vec![0, 1]))));
+        assert_eq!(cmd.distribution().unwrap().chance(1), ratio(1, 2));
+    }
+
+    #[test]
+    fn rollcmds_render_canonically() {
+        for s in &["2d6", "4dF.1kh2", "1d%b2>50", "3d{hit,miss}", "6d10>8f<1r=2!!>9kh4", "1d{-1,0,1}!p"] {
+            let cmd: RollCmd = s.parse().unwrap();
+            assert_eq!(cmd.notation().parse::<RollCmd>(), Ok(cmd.clone()), "for {:?}", s);
+        }
+        assert_eq!("d%".parse::<RollCmd>().unwrap().notation(), "1d100");
+    }
+
+    #[test]
+    fn dice_need_sides() {
+        assert_eq!(RollCmd::try_new(3, 0), Err(DieError::ZeroSides));
+        assert_eq!(RollCmd::try_from_die(1, Die::Faces(vec![])), Err(DieError::ZeroSides));
+        assert_eq!(RollCmd::try_from_die(1, Die::Symbols(vec![])), Err(DieError::ZeroSides));
+        let nil = CustomDie::new("Nil", Die::Standard(0));
+        assert_eq!(RollCmd::try_from_die(1, Die::Custom(nil)), Err(DieError::ZeroSides));
+        assert_eq!(RollCmd::try_new(0, 1), Ok(RollCmd::new(0, 1)));
+    }
+
+    #[test]
+    #[should_panic(expected = "at least one side")]
+    fn new_rejects_zero_sides() {
+        RollCmd::new(3, 0);
+    }
+
+    #[test]
+    fn rolling_no_dice_totals_zero() {
+        for s in &["0d6", "0d6kh3", "0d6dl1!", "0d10>8f1", "0dF", "0d{hit,miss}"] {
+            let cmd: RollCmd = s.parse().unwrap();
+            let result = cmd.result(&mut Fixed::new(vec![]));
+            assert!(result.iter().next().is_none(), "{}", s);
+            assert_eq!(result.total(), 0, "{}", s);
+            assert_eq!((cmd.min(), cmd.max()), (0, 0), "{}", s);
+            assert_eq!(cmd.distribution(), Some(Distribution::constant(0)), "{}", s);
+        }
+        assert_eq!(RollCmd::new(0, 6).result(&mut Fixed::new(vec![])).to_string(), "No dice (Total: 0)");
+    }
+
+    #[test]
+    fn edge_values_never_panic() {
+        let unlimited = Registry::new().max_count(u32::MAX).max_sides(u32::MAX);
+        let rollable = Registry::new().max_sides(u32::MAX);
+        let edges = ["", "0", "1", "4294967295"];
+        let modifiers = ["", "kh0", "kh4294967295", "dl4294967295", "!", "!!>1", "!p", "r1", "ro<4294967295",
+                         ">0", ">4294967295f1", "<1"];
+        for count in &edges {
+            for sides in &edges[1..] {
+                for modifier in &modifiers {
+                    let s = format!("{}d{}{}", count, sides, modifier);
+                    let _ = s.parse::<Expr>();
+                    if let Ok(expr) = unlimited.parse_expr(&s) {
+                        assert_eq!(unlimited.parse_expr(&expr.to_string()), Ok(expr), "{}", s);
+                    }
+                    let expr = match rollable.parse_expr(&s) {
+                        Ok(expr) => expr,
+                        Err(_) => continue,
+                    };
+                    let result = expr.result(&mut SeededRng::new(0)).unwrap();
+                    let _ = result.to_string();
+                    if *sides != "4294967295" {
+                        assert!(expr.min() <= result.total() && result.total() <= expr.max(), "{}", s);
+                    }
+                }
+            }
+        }
+        for s in &["4294967295*0d6", "-0d1", "4294967295-4294967295", "0d1/1d1"] {
+            let expr: Expr = s.parse().unwrap();
+            assert_eq!(expr.result(&mut SeededRng::new(0)).unwrap().total(), 0, "{}", s);
+        }
+        assert_eq!("0d1/0d1".parse::<Expr>().unwrap().result(&mut MaxRng).unwrap_err(), EvalError::DivideByZero);
+    }
+
+    #[test]
+    fn rejects_malformed_rollcmds() {
+        for s in &["2d", "d", "2dd6", "2dx6", "d6d", "0d0", "2 d6", "2d6+1", "4d6kh3kl1", "4d6 kh3",
+                   "d6!!!", "d6!x", "d6!>", "d6! >5", "d6r", "d6ro", "d6r1r2",
+                   "10d10>8>9", "10d10f1", "10d10>8f", "10d10>",
+                   "4df", "4dF.3", "4dF.", "4dF6", "dFr>=-1"] {
+            assert!(s.parse::<RollCmd>().is_err(), "accepted {:?}", s);
+        }
+    }
 }
@@ -0,0 +1,161 @@
+//! The interactive prompt `dice` starts when it's given nothing to roll.
+//!
+//! Each line takes the same arguments as the command line, so `4d6kh3
+//! 1d20+5` rolls two expressions and `stats 2d6` prints a table, and any
+//! options the prompt was started with, like `--ladder`, apply to every
+//! line. A `#` comments on the last expression before it, and a line that
+//! is all comment does nothing. `!!` on its own repeats the last line, `help`
+//! explains all this, and `exit`, `quit` or Ctrl-D leave. Errors are printed
+//! and the prompt carries on.
+//!
+//! Every line rolls with a seed of its own drawn from the prompt's seed, so a
+//! whole session can be replayed with `--seed` and `--verbose` shows the seed
+//! that rolls a single line again. History is kept in the file named by
+//! `DICE_HISTORY`, or else `.dice_history` in the home directory.
+
+use std::env;
+use std::path::PathBuf;
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+use cli::{self, Options};
+use parse;
+use {Registry, SeededRng};
+
+const PROMPT: &str = "dice> ";
+
+const HELP: &str = "\
+Type dice expressions separated by spaces to roll them, e.g. 4d6kh3 1d20+5.
+Start with stats or compare for the odds instead, e.g. stats 2d6.
+Label a roll or comment on it with sword: 1d8+3 # magic.
+Options like --ladder or --verbose work just as on the command line.
+
+  !!     repeat the last line
+  help   show this message
+  exit   leave, as does quit or Ctrl-D";
+
+/// Runs the prompt until the user leaves. `base` holds the arguments the
+/// prompt was started with, and `seed` the seed among them, if any.
+///
+/// Returns the exit code: 0 unless the prompt couldn't start.
+pub(crate) fn run(base: &[String], seed: Option<u64>, registry: &Registry) -> i32 {
+    let mut seeds = match seed.map_or_else(cli::random_seed, Ok) {
+        Ok(seed) => SeededRng::new(seed),
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 1;
+        }
+    };
+    let history = history_file();
+    if let Some(ref path) = history {
+        // There's nothing to load the first time round
+        let _ = editor.load_history(path);
+    }
+
+    let mut last: Option<String> = None;
+    loop {
+        let line = match editor.readline(PROMPT) {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => {
+                eprintln!("{}", e);
+                break;
+            }
+        };
+        let line = match line.trim() {
+            "" => continue,
+            line if line.starts_with('#') => continue,
+            "exit" | "quit" => break,
+            "help" => {
+                println!("{}", HELP);
+                continue;
+            }
+            "!!" => match last {
+                Some(ref last) => {
+                    println!("{}", last);
+                    last.clone()
+                }
+                None => {
+                    eprintln!("No line to repeat yet");
+                    continue;
+                }
+            },
+            line => line.to_string(),
+        };
+        let _ = editor.add_history_entry(line.as_str());
+        roll_line(base, &line, seeds.next_u64(), registry);
+        last = Some(line);
+    }
+
+    if let Some(ref path) = history {
+        if let Err(e) = editor.save_history(path) {
+            eprintln!("Couldn't save history to {}: {}", path.display(), e);
+        }
+    }
+    0
+}
+
+/// Runs a line with `seed`, unless it gives a seed of its own.
+fn roll_line(base: &[String], line: &str, seed: u64, registry: &Registry) {
+    let mut opts = match Options::from_args(line_args(base, line).into_iter()) {
+        Ok(opts) => opts,
+        Err(e) => return eprintln!("{}", e),
+    };
+    if !line.split_whitespace().any(|word| word == "--seed") {
+        opts.seed = Some(seed);
+    }
+    cli::execute(opts, registry);
+}
+
+/// The arguments a line stands for: its own words after the prompt's, but
+/// with any `stats` or `compare` still first, and any comment kept with the
+/// word before it.
+fn line_args(base: &[String], line: &str) -> Vec<String> {
+    let (line, comment) = match parse::outside_braces(line, '#') {
+        Some(i) => (&line[..i], Some(&line[i..])),
+        None => (line, None),
+    };
+    let mut args: Vec<String> = line.split_whitespace().map(String::from).collect();
+    if let (Some(last), Some(comment)) = (args.last_mut(), comment) {
+        last.push(' ');
+        last.push_str(comment);
+    }
+    let at = match args.first().map(String::as_str) {
+        Some("stats") | Some("compare") => 1,
+        _ => 0,
+    };
+    args.splice(at..at, base.iter().cloned());
+    args
+}
+
+/// Where history is kept, if anywhere.
+fn history_file() -> Option<PathBuf> {
+    env::var_os("DICE_HISTORY")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".dice_history")))
+}
+
+#[cfg(test)]
+mod repl_tests {
+    use super::*;
+
+    #[test]
+    fn lines_follow_the_prompts_options() {
+        let base = vec!["--ladder".to_string()];
+        assert_eq!(line_args(&base, " 4d6kh3  1d20+5 "), ["--ladder", "4d6kh3", "1d20+5"]);
+        assert_eq!(line_args(&base, "stats 2d6"), ["stats", "--ladder", "2d6"]);
+        assert_eq!(line_args(&[], "compare 2d6 1d12"), ["compare", "2d6", "1d12"]);
+        assert_eq!(line_args(&[], "4d6kh3 1d20+5 # Perception"), ["4d6kh3", "1d20+5 # Perception"]);
+        assert_eq!(line_args(&[], "d{a#b} 2d{#,c}# odd"), ["d{a#b}", "2d{#,c} # odd"]);
+        assert_eq!(line_args(&[], "d{a#b}"), ["d{a#b}"]);
+    }
+}
@@ -0,0 +1,245 @@
+//! Sources of randomness for rolling dice.
+
+use rand::Rng;
+
+/// Something that rolls a single die.
+///
+/// Every roll in this library comes down to `roll_die`, so implementing it is
+/// all it takes to roll with a source of your own.
+pub trait DiceRng {
+    /// Rolls a die with `sides` faces numbered from 1, returning a value in
+    /// `1..=sides`. `sides` is never 0.
+    fn roll_die(&mut self, sides: u32) -> u32;
+}
+
+impl<R: DiceRng + ?Sized> DiceRng for &mut R {
+    fn roll_die(&mut self, sides: u32) -> u32 {
+        (**self).roll_die(sides)
+    }
+}
+
+/// Rolls with any `rand::Rng`.
+///
+/// # Examples
+///
+/// ```
+/// extern crate rand;
+/// extern crate rcmd;
+///
+/// use rcmd::{DiceRng, RandRng};
+///
+/// fn main() {
+///     let mut rng = RandRng(rand::thread_rng());
+///     let roll = rng.roll_die(6);
+///     assert!((1..=6).contains(&roll));
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct RandRng<R>(pub R);
+
+impl<R: Rng> DiceRng for RandRng<R> {
+    fn roll_die(&mut self, sides: u32) -> u32 {
+        self.0.gen_range(0, sides) + 1
+    }
+}
+
+/// Rolls reproducibly from a 64-bit seed.
+///
+/// The same seed rolls the same dice on every platform and in every release
+/// of this library, so a roll can be checked later by anyone with the seed.
+/// To keep that promise the algorithm is fixed as follows:
+///
+/// * The generator is SplitMix64. Each step adds `0x9e3779b97f4a7c15` to a
+///   64-bit state, wrapping, then mixes the new state `z` into an output with
+///   `z = (z ^ (z >> 30)) * 0xbf58476d1ce4e5b9`,
+///   `z = (z ^ (z >> 27)) * 0x94d049bb133111eb` and `z ^ (z >> 31)`,
+///   multiplying with wrapping. The state starts as the seed.
+/// * A die with `sides` faces takes the next output `x` below the largest
+///   multiple of `sides` that fits in 64 bits, skipping any others so that
+///   every face is equally likely, and rolls `x % sides + 1`.
+///
+/// # Examples
+///
+/// ```
+/// use rcmd::{RollCmd, SeededRng};
+/// let a = RollCmd::new(4, 6).result(&mut SeededRng::new(1234));
+/// let b = RollCmd::new(4, 6).result(&mut SeededRng::new(1234));
+/// assert!(a.values() == b.values());
+/// ```
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SeededRng {
+    state: u64,
+}
+
+impl SeededRng {
+    // Construct a SeededRng that starts from seed.
+    pub fn new(seed: u64) -> SeededRng {
+        SeededRng { state: seed }
+    }
+
+    /// The next raw 64-bit output of the generator.
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl DiceRng for SeededRng {
+    fn roll_die(&mut self, sides: u32) -> u32 {
+        let sides = u64::from(sides);
+        let limit = u64::MAX - u64::MAX % sides;
+        loop {
+            let x = self.next_u64();
+            if x < limit {
+                return (x % sides) as u32 + 1;
+            }
+        }
+    }
+}
+
+/// Rolls a fixed sequence of values, for tests.
+///
+/// # Panics
+///
+/// When the sequence runs out, or a value doesn't fit the die being rolled.
+///
+/// # Examples
+///
+/// ```
+/// use rcmd::{Fixed, RollCmd};
+/// let result = RollCmd::new(3, 6).result(&mut Fixed::new(vec![2, 3, 3]));
+/// assert!(result.values() == [2, 3, 3]);
+/// ```
+#[derive(Clone, Debug)]
+pub struct Fixed {
+    rolls: Vec<u32>,
+    next: usize,
+}
+
+impl Fixed {
+    // Construct a Fixed that rolls each value in turn.
+    pub fn new(rolls: Vec<u32>) -> Fixed {
+        Fixed { rolls, next: 0 }
+    }
+
+    /// Whether every value has been rolled.
+    pub fn is_done(&self) -> bool {
+        self.next == self.rolls.len()
+    }
+}
+
+impl DiceRng for Fixed {
+    fn roll_die(&mut self, sides: u32) -> u32 {
+        let roll = match self.rolls.get(self.next) {
+            Some(&roll) => roll,
+            None => panic!("fixed rolls ran out after {}", self.rolls.len()),
+        };
+        assert!((1..=sides).contains(&roll), "fixed roll {} doesn't fit a d{}", roll, sides);
+        self.next += 1;
+        roll
+    }
+}
+
+/// Rolls the highest face of every die, for previews.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MaxRng;
+
+impl DiceRng for MaxRng {
+    fn roll_die(&mut self, sides: u32) -> u32 {
+        sides
+    }
+}
+
+/// Rolls a 1 on every die, for previews.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MinRng;
+
+impl DiceRng for MinRng {
+    fn roll_die(&mut self, _sides: u32) -> u32 {
+        1
+    }
+}
+
+/// Rolls the middle face of every die, for previews.
+///
+/// A die with an even number of sides has two middle faces, so these dice
+/// alternate between rounding down and up; two d6 roll a 3 and a 4.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AverageRng {
+    up: bool,
+}
+
+impl AverageRng {
+    // Construct an AverageRng that rounds down first.
+    pub fn new() -> AverageRng {
+        AverageRng::default()
+    }
+}
+
+impl DiceRng for AverageRng {
+    fn roll_die(&mut self, sides: u32) -> u32 {
+        if sides % 2 == 1 {
+            return sides / 2 + 1;
+        }
+        self.up = !self.up;
+        if self.up { sides / 2 } else { sides / 2 + 1 }
+    }
+}
+
+#[cfg(test)]
+mod rng_tests {
+    use super::*;
+
+    #[test]
+    fn fixed_rolls_in_order() {
+        let mut rng = Fixed::new(vec![4, 1]);
+        assert_eq!((rng.roll_die(6), rng.roll_die(6)), (4, 1));
+        assert!(rng.is_done());
+    }
+
+    #[test]
+    #[should_panic(expected = "ran out")]
+    fn fixed_rolls_run_out() {
+        Fixed::new(vec![]).roll_die(6);
+    }
+
+    #[test]
+    #[should_panic(expected = "doesn't fit")]
+    fn fixed_rolls_must_fit_the_die() {
+        Fixed::new(vec![7]).roll_die(6);
+    }
+
+    #[test]
+    fn previews_roll_the_ends_and_middle() {
+        assert_eq!((MinRng.roll_die(20), MaxRng.roll_die(20)), (1, 20));
+
+        let mut rng = AverageRng::new();
+        let rolls: Vec<_> = (0..4).map(|_| rng.roll_die(6)).collect();
+        assert_eq!(rolls, [3, 4, 3, 4]);
+        assert_eq!(rng.roll_die(5), 3);
+    }
+
+    #[test]
+    fn seeded_rolls_never_change() {
+        // Published SplitMix64 outputs for a zero seed
+        let mut rng = SeededRng::new(0);
+        assert_eq!(rng.next_u64(), 0xe220_a839_7b1d_cdaf);
+        assert_eq!(rng.next_u64(), 0x6e78_9e6a_a1b9_65f4);
+        assert_eq!(rng.next_u64(), 0x06c4_5d18_8009_454f);
+
+        // Changing these breaks every roll anyone has recorded with a seed
+        let mut rng = SeededRng::new(1234);
+        let rolls: Vec<_> = (0..8).map(|_| rng.roll_die(6)).collect();
+        assert_eq!(rolls, [2, 3, 5, 4, 4, 6, 5, 6]);
+        assert_eq!(SeededRng::new(1234).roll_die(100), 96);
+    }
+
+    #[test]
+    fn rand_rolls_stay_on_the_die() {
+        let mut rng = RandRng(::rand::thread_rng());
+        assert!((0..100).map(|_| rng.roll_die(6)).all(|n| (1..=6).contains(&n)));
+    }
+}
@@ -0,0 +1,111 @@
+//! A common interface to everything that can be rolled.
+
+use std::ops::Range;
+
+use distribution::Distribution;
+use rng::DiceRng;
+
+/// Something that can be rolled: a single `Die`, a `RollCmd` of several
+/// dice, or a whole `Expr`.
+///
+/// Implement this for a die type of your own and register it with a
+/// `Registry` to use it from the parser and the command line.
+///
+/// # Examples
+///
+/// ```
+/// use rcmd::{Die, MaxRng, Rollable};
+/// let d6 = Die::Standard(6);
+/// assert!(d6.roll(&mut MaxRng) == 6);
+/// assert!((d6.min(), d6.max()) == (1, 6));
+/// assert!(d6.expected() == 3.5);
+/// assert!(d6.notation() == "d6");
+/// ```
+pub trait Rollable {
+    /// What a single roll produces.
+    type Output;
+
+    /// Rolls once, asking `rng` for randomness.
+    fn roll(&self, rng: &mut dyn DiceRng) -> Self::Output;
+
+    /// The lowest total a roll can come to.
+    fn min(&self) -> i64;
+
+    /// The highest total a roll can come to.
+    fn max(&self) -> i64;
+
+    /// The average total over many rolls.
+    fn expected(&self) -> f64;
+
+    /// The dice notation that parses back to this, e.g. `4d6kh3`.
+    fn notation(&self) -> String;
+
+    /// The exact chance of each possible total, when it's known. Defaults
+    /// to `None`.
+    fn distribution(&self) -> Option<Distribution> {
+        None
+    }
+
+    /// Whether there's nothing to roll, like a die without faces, so that
+    /// `roll` can't be called. Defaults to `false`.
+    fn is_empty(&self) -> bool {
+        false
+    }
+}
+
+/// The expected value of `score` summed over the dice ranked `ranks` (from
+/// lowest, counting from 0) out of `n` dice rolled from `pmf`.
+///
+/// The chance that the die ranked j is at most v is the chance that more
+/// than j of the n dice are at most v, so summing over the kept ranks only
+/// needs one binomial distribution per value.
+pub(crate) fn kept_expected<G: Fn(i64) -> f64>(pmf: &[(i64, f64)], n: u32, ranks: Range<usize>, score: G) -> f64 {
+    let mut expected = 0.0;
+    let (mut cdf, mut below) = (0.0, 0.0);
+    for &(v, p) in pmf {
+        cdf += p;
+        let at_most = ranks_at_most(n, cdf.min(1.0), &ranks);
+        expected += score(v) * (at_most - below);
+        below = at_most;
+    }
+    expected
+}
+
+/// How many of the kept ranks we expect to be filled by dice that are each
+/// at most some value with probability `p`.
+fn ranks_at_most(n: u32, p: f64, ranks: &Range<usize>) -> f64 {
+    let filled = |i: u32| (i as usize).saturating_sub(ranks.start).min(ranks.len()) as f64;
+    if p <= 0.0 {
+        return filled(0);
+    }
+    if p >= 1.0 {
+        return filled(n);
+    }
+
+    // Binomial chances worked in logs so that large counts don't underflow
+    let (ln_p, ln_q) = (p.ln(), (1.0 - p).ln());
+    let mut ln_choose = 0.0;
+    let mut total = 0.0;
+    for i in 0..=n {
+        if i > 0 {
+            ln_choose += f64::from(n - i + 1).ln() - f64::from(i).ln();
+        }
+        let chance = (ln_choose + f64::from(i) * ln_p + f64::from(n - i) * ln_q).exp();
+        total += chance * filled(i);
+    }
+    total
+}
+
+#[cfg(test)]
+mod rollable_tests {
+    use super::*;
+
+    #[test]
+    fn kept_dice_follow_order_statistics() {
+        let d2 = [(1, 0.5), (2, 0.5)];
+        // Highest of two coins is 2 unless both are 1
+        assert!((kept_expected(&d2, 2, 1..2, |v| v as f64) - 1.75).abs() < 1e-12);
+        assert!((kept_expected(&d2, 2, 0..1, |v| v as f64) - 1.25).abs() < 1e-12);
+        assert!((kept_expected(&d2, 2, 0..2, |v| v as f64) - 3.0).abs() < 1e-12);
+    }
+}